@@ -0,0 +1,195 @@
+//! Native client for the game protocol, for bots, tests and tooling.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), client::Error> {
+//! use shared::Coordinate;
+//!
+//! let mut client = client::Client::connect("wss://api.imaginarium.monster/ws").await?;
+//!
+//! client.move_to(Coordinate { x: 4, y: 2 }).await?;
+//!
+//! while let Some(message) = client.next_event().await {
+//!     println!("{:?}", message?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bincode::error::DecodeError;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, Stream, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use shared::{Coordinate, PlayerData, SystemMessages};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(tokio_tungstenite::tungstenite::Error),
+    Decode(DecodeError),
+    /// The server closed the connection before spawning the player, with its reason if it gave one
+    Rejected(Option<SystemMessages>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::WebSocket(error) => write!(f, "websocket error: {}", error),
+            Error::Decode(error) => write!(f, "invalid message: {}", error),
+            Error::Rejected(Some(message)) => write!(f, "rejected by the server: {:?}", message),
+            Error::Rejected(None) => write!(f, "connection closed before the player was spawned"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(error)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(error: DecodeError) -> Self {
+        Error::Decode(error)
+    }
+}
+
+/// A connected player
+pub struct Client {
+    player: PlayerData,
+    actions: Actions,
+    events: Events,
+}
+
+impl Client {
+    /// Connect and wait until the main player is spawned, which may take a while when the world is full
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let (websocket, _) = connect_async(url).await?;
+        let (sink, stream) = websocket.split();
+
+        let mut events = Events { stream };
+        let mut last = None;
+
+        while let Some(message) = events.next().await {
+            match message? {
+                SystemMessages::MainPlayerSpawn { data } => {
+                    return Ok(Self { player: data, actions: Actions { sink, sequence: 0 }, events });
+                }
+                message => last = Some(message),
+            }
+        }
+
+        // Only a message explaining the rejection is worth reporting
+        let reason = last.filter(|message| matches!(
+            message,
+            SystemMessages::Evicted { .. } | SystemMessages::ServerShuttingDown { .. }
+        ));
+
+        Err(Error::Rejected(reason))
+    }
+
+    /// The main player as it was spawned
+    pub fn player(&self) -> &PlayerData {
+        &self.player
+    }
+
+    /// Returns the sequence number of the move, acknowledged by [`SystemMessages::MoveAcknowledged`]
+    pub async fn move_to(&mut self, coordinate: Coordinate) -> Result<u32, Error> {
+        self.actions.move_to(coordinate).await
+    }
+
+    pub async fn pick_up(&mut self) -> Result<(), Error> {
+        self.actions.pick_up().await
+    }
+
+    /// Build a monument on the coordinate, it must be close to the player and clear of other monuments
+    pub async fn build(&mut self, prompt: impl Into<String>, coordinate: Coordinate) -> Result<(), Error> {
+        self.actions.build(prompt, coordinate).await
+    }
+
+    pub async fn send(&mut self, message: SystemMessages) -> Result<(), Error> {
+        self.actions.send(message).await
+    }
+
+    /// Next message from the server, `None` once the connection is closed
+    pub async fn next_event(&mut self) -> Option<Result<SystemMessages, Error>> {
+        self.events.next().await
+    }
+
+    /// Separate both halves so messages can be sent while another task consumes the events
+    pub fn split(self) -> (Actions, Events) {
+        (self.actions, self.events)
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.actions.close().await
+    }
+}
+
+/// Sending half of a [`Client`]
+pub struct Actions {
+    sink: SplitSink<Socket, Message>,
+    /// Of the last move sent
+    sequence: u32,
+}
+
+impl Actions {
+    pub async fn move_to(&mut self, coordinate: Coordinate) -> Result<u32, Error> {
+        self.sequence += 1;
+        self.send(SystemMessages::PlayerPosition { coordinate, sequence: self.sequence }).await?;
+
+        Ok(self.sequence)
+    }
+
+    pub async fn pick_up(&mut self) -> Result<(), Error> {
+        self.send(SystemMessages::MainPlayerPickedUpToken).await
+    }
+
+    pub async fn build(&mut self, prompt: impl Into<String>, coordinate: Coordinate) -> Result<(), Error> {
+        self.send(SystemMessages::BuildMonumentRequest { prompt: prompt.into(), coordinate }).await
+    }
+
+    pub async fn send(&mut self, message: SystemMessages) -> Result<(), Error> {
+        Ok(self.sink.send(message.into()).await?)
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        Ok(self.sink.close().await?)
+    }
+}
+
+/// Receiving half of a [`Client`], a stream of every message sent by the server
+pub struct Events {
+    stream: SplitStream<Socket>,
+}
+
+impl Stream for Events {
+    type Item = Result<SystemMessages, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let frame = match self.stream.poll_next_unpin(context) {
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Some(Err(error.into()))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match frame {
+                Message::Binary(_) => return Poll::Ready(Some(SystemMessages::try_from(frame).map_err(Error::from))),
+                Message::Close(_) => return Poll::Ready(None),
+                // Pings are answered by tungstenite itself
+                _ => continue,
+            }
+        }
+    }
+}
@@ -8,9 +8,9 @@ export function registerFunction<T>(name: string, func: Function): (value: T) =>
 
     let resolver: ((value: T) => void) | null = null
 
-    window[ name ] = () => {
+    window[ name ] = (...args: any[]) => {
 
-        func()
+        func(...args)
 
         return new Promise(resolve => resolver = resolve)
 
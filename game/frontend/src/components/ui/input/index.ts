@@ -0,0 +1 @@
+export { default as Input } from './Input.vue'
\ No newline at end of file
@@ -1,12 +1,18 @@
 use crate::js_bridge_plugin::{JSBridgeMessages, JsBridgeMessageReceived};
-use crate::network::{SendWebSocketMessage, WebSocketMessageReceived};
+use crate::network::{Reconnected, SendWebSocketMessage, WebSocketMessageReceived};
+use bevy::color::palettes::tailwind::GRAY_800;
+use bevy::picking::mesh_picking::MeshPickingPlugin;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
 use bevy_kira_audio::{Audio, AudioControl};
 use bevy_sprite3d::{Sprite3dBuilder, Sprite3dParams};
 use futures_util::SinkExt;
-use shared::{Monument, SystemMessages};
+use shared::{Coordinate, Monument, SystemMessages, MAX_PLACEMENT_DISTANCE, MONUMENT_FOOTPRINT};
+use crate::js_bridge_plugin::SendJsBridgeMessage;
+use crate::physics::SpriteImage;
+use crate::robot::Player;
 use crate::sound_effects::AudioCache;
+use crate::ui::{handle_build_monument_button_state_system, reset_ui_blocker, UiInputBlocker};
 
 pub struct BuilderPlugin;
 
@@ -15,12 +21,60 @@ struct MonumentShake {
     shake_timer: f32,
 }
 
+#[derive(Component)]
+struct MonumentTooltip;
+
+/// Monument under the cursor, if any
+#[derive(Resource, Default)]
+struct HoveredMonument(Option<Entity>);
+
+/// Where the next monument goes, picked on the ground before describing it
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    #[default]
+    Inactive,
+    Picking,
+    Chosen(Coordinate),
+}
+
+/// Monuments sent by the server, spawned once their image is loaded
+#[derive(Resource, Default)]
+struct LoadingMonuments(HashMap<u32, (Monument, Handle<Image>)>);
+
+/// Final images of completed monuments, swapped in once loaded
+#[derive(Resource, Default)]
+struct LoadingCompletions(HashMap<Entity, Handle<Image>>);
+
+/// Footprint of the monument being placed, follows the cursor
+#[derive(Component)]
+struct PlacementGhost;
+
+#[derive(Resource)]
+struct GhostMaterials {
+    clear: Handle<StandardMaterial>,
+    blocked: Handle<StandardMaterial>,
+}
+
 impl Plugin for BuilderPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, build_monument_system);
+        app.insert_resource(LoadingMonuments::default());
+        app.insert_resource(LoadingCompletions::default());
         app.add_systems(Update, sync_monument_system);
         app.add_systems(Update, update_under_construction_monument_system);
         app.add_systems(Update, animate_monument_system);
+        app.add_systems(Update, remove_monument_system);
+        app.add_systems(Update, forget_monuments_on_reconnect_system);
+
+        app.add_plugins(MeshPickingPlugin);
+        app.insert_resource(HoveredMonument::default());
+        app.add_systems(Startup, add_monument_tooltip_system);
+        app.add_systems(Update, update_monument_tooltip_system);
+
+        app.insert_resource(Placement::default());
+        app.add_systems(Startup, add_placement_ghost_system);
+        app.add_systems(Update, place_monument_system.after(reset_ui_blocker).before(handle_build_monument_button_state_system));
+        app.add_systems(Update, listen_for_build_rejected_system);
     }
 }
 
@@ -30,8 +84,10 @@ fn update_under_construction_monument_system(
     mut monuments: Query<(Entity, &mut Monument)>,
     mut events: EventReader<WebSocketMessageReceived>,
     asset_server: Res<AssetServer>,
-    mut queue: Local<HashMap<Entity, Handle<Image>>>,
+    mut loading: ResMut<LoadingCompletions>,
 ) {
+    let queue = &mut loading.0;
+
     for event in events.read() {
         if let SystemMessages::MonumentCompleted { id, asset } = &event.0 {
             if let Some((entity, mut monument)) = monuments.iter_mut().find(|(_, monument)| &monument.id == id) {
@@ -52,7 +108,14 @@ fn update_under_construction_monument_system(
 
     for (entity, image) in ready_to_spawn {
         queue.remove(&entity);
-        commands.entity(entity).insert(
+
+        // Its chunk was unloaded while the image was loading
+        if monuments.contains(entity) == false {
+            continue;
+        }
+
+        commands.entity(entity).insert((
+            SpriteImage(image.clone()),
             Sprite3dBuilder {
                 image,
                 pixels_per_metre: 100.,
@@ -60,8 +123,8 @@ fn update_under_construction_monument_system(
                 unlit: true,
                 pivot: Some(Vec2::new(0.5, 0.0)),
                 ..default()
-            }.bundle(&mut sprite_params)
-        );
+            }.bundle(&mut sprite_params),
+        ));
     }
 }
 
@@ -70,13 +133,25 @@ fn sync_monument_system(
     mut sprite_params: Sprite3dParams,
     mut events: EventReader<WebSocketMessageReceived>,
     asset_server: Res<AssetServer>,
-    mut queue: Local<HashMap<u32, (Monument, Handle<Image>)>>,
+    mut loading: ResMut<LoadingMonuments>,
     audio_cache: Res<AudioCache>,
     audio: Res<Audio>,
 ) {
+    let queue = &mut loading.0;
+
     for event in events.read() {
-        if let SystemMessages::BuildMonument { monument } = &event.0 {
-            queue.entry(monument.id).or_insert((monument.clone(), asset_server.load(&monument.asset)));
+        match &event.0 {
+            SystemMessages::BuildMonument { monument } => {
+                queue.entry(monument.id).or_insert((monument.clone(), asset_server.load(&monument.asset)));
+            }
+            // Removed before its image even finished loading
+            SystemMessages::MonumentRemoved { id } => {
+                queue.remove(id);
+            }
+            SystemMessages::ChunkUnloaded { chunk } => {
+                queue.retain(|_, (monument, _)| monument.position.chunk() != *chunk);
+            }
+            _ => {}
         }
     }
 
@@ -95,13 +170,167 @@ fn sync_monument_system(
     }
 }
 
+fn remove_monument_system(
+    mut commands: Commands,
+    monuments: Query<(Entity, &Monument)>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        match &event.0 {
+            SystemMessages::MonumentRemoved { id } => {
+                for (entity, _) in monuments.iter().filter(|(_, monument)| &monument.id == id) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            // The player walked away, the server streams it again when they come back
+            SystemMessages::ChunkUnloaded { chunk } => {
+                for (entity, _) in monuments.iter().filter(|(_, monument)| monument.position.chunk() == *chunk) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Monuments around the new main player are streamed again once it spawns
+fn forget_monuments_on_reconnect_system(
+    mut commands: Commands,
+    monuments: Query<Entity, With<Monument>>,
+    mut placement: ResMut<Placement>,
+    mut hovered: ResMut<HoveredMonument>,
+    mut loading_monuments: ResMut<LoadingMonuments>,
+    mut loading_completions: ResMut<LoadingCompletions>,
+    mut events: EventReader<Reconnected>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    for monument in &monuments {
+        commands.entity(monument).despawn_recursive();
+    }
+
+    // Still loading from the previous session, they would pop up out of nowhere
+    loading_monuments.0.clear();
+    loading_completions.0.clear();
+
+    *placement = Placement::Inactive;
+    hovered.0 = None;
+}
+
 fn build_monument_system(
     mut websocket: EventWriter<SendWebSocketMessage>,
     mut js_bridge_events: EventReader<JsBridgeMessageReceived>,
+    mut placement: ResMut<Placement>,
 ) {
     for js_bridge_event in js_bridge_events.read() {
-        if let JSBridgeMessages::CallOpenModalResponse(Some(prompt)) = &js_bridge_event.0 {
-            websocket.send(SendWebSocketMessage(SystemMessages::BuildMonumentRequest { prompt: prompt.clone() }));
+        if let JSBridgeMessages::CallOpenModalResponse(response) = &js_bridge_event.0 {
+            if let (Some(prompt), Placement::Chosen(coordinate)) = (response, *placement) {
+                websocket.send(SendWebSocketMessage(SystemMessages::BuildMonumentRequest { prompt: prompt.clone(), coordinate }));
+            }
+
+            *placement = Placement::Inactive;
+        }
+    }
+}
+
+fn add_placement_ghost_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let side = (MONUMENT_FOOTPRINT * 2 + 1) as f32;
+
+    let ghost_material = |color: Color| StandardMaterial {
+        base_color: color,
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    };
+
+    let clear = materials.add(ghost_material(Color::srgba(0.2, 0.8, 0.3, 0.4)));
+    let blocked = materials.add(ghost_material(Color::srgba(0.9, 0.2, 0.2, 0.4)));
+
+    commands.spawn((
+        PlacementGhost,
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(side, side))),
+        MeshMaterial3d(clear.clone()),
+        Transform::from_xyz(0.0, 0.02, 0.0),
+        Visibility::Hidden,
+    ));
+
+    commands.insert_resource(GhostMaterials { clear, blocked });
+}
+
+/// While picking a spot, the ghost follows the cursor and a click on clear ground opens the prompt
+fn place_monument_system(
+    mut placement: ResMut<Placement>,
+    mut blocker: ResMut<UiInputBlocker>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    windows: Query<&Window>,
+    camera: Query<(&Camera, &GlobalTransform), With<Projection>>,
+    player: Query<&Transform, (With<Player>, Without<PlacementGhost>)>,
+    monuments: Query<&Monument>,
+    ghost_materials: Res<GhostMaterials>,
+    mut ghost: Query<(&mut Transform, &mut MeshMaterial3d<StandardMaterial>, &mut Visibility), With<PlacementGhost>>,
+    mut event: EventWriter<SendJsBridgeMessage>,
+) {
+    let Ok((mut transform, mut material, mut visibility)) = ghost.get_single_mut() else {
+        return;
+    };
+
+    if *placement != Placement::Picking {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+
+    // Keep the click from also moving the robot
+    blocker.0 = true;
+
+    if keyboard.just_pressed(KeyCode::Escape) || mouse.just_pressed(MouseButton::Right) {
+        *placement = Placement::Inactive;
+        return;
+    }
+
+    let Some(coordinate) = cursor_coordinate(&windows, &camera) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    let in_reach = player
+        .get_single()
+        .is_ok_and(|transform| Coordinate::from(transform.translation).distance(&coordinate) <= MAX_PLACEMENT_DISTANCE);
+
+    let clear = in_reach && monuments.iter().all(|monument| monument.position.footprints_overlap(&coordinate) == false);
+
+    transform.translation = coordinate.to_vec3().with_y(0.02);
+    material.0 = if clear { ghost_materials.clear.clone() } else { ghost_materials.blocked.clone() };
+    *visibility = Visibility::Visible;
+
+    if clear && mouse.just_pressed(MouseButton::Left) {
+        *placement = Placement::Chosen(coordinate);
+        event.send(SendJsBridgeMessage(JSBridgeMessages::CallOpenModal));
+    }
+}
+
+/// Tile of the ground under the cursor
+fn cursor_coordinate(windows: &Query<&Window>, camera: &Query<(&Camera, &GlobalTransform), With<Projection>>) -> Option<Coordinate> {
+    let cursor_position = windows.get_single().ok()?.cursor_position()?;
+    let (camera, camera_transform) = camera.get_single().ok()?;
+    let ray = camera.viewport_to_world(camera_transform, cursor_position).ok()?;
+    let distance = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))?;
+
+    let point = ray.origin + ray.direction * distance;
+
+    Some(Coordinate { x: point.x.round() as i32, y: point.z.round() as i32 })
+}
+
+fn listen_for_build_rejected_system(mut events: EventReader<WebSocketMessageReceived>) {
+    for event in events.read() {
+        if let SystemMessages::BuildMonumentRejected { reason } = &event.0 {
+            warn!("monument rejected: {}", reason);
         }
     }
 }
@@ -113,6 +342,7 @@ fn spawn_monument(
     image_handle: Handle<Image>,
 ) {
     commands.spawn((
+        SpriteImage(image_handle.clone()),
         Sprite3dBuilder {
             image: image_handle,
             pixels_per_metre: 100.,
@@ -129,8 +359,92 @@ fn spawn_monument(
         },
         monument,
         MonumentShake { shake_timer: 0.0 },
+    ))
+    .observe(monument_hovered_observer)
+    .observe(monument_left_observer);
+}
+
+fn monument_hovered_observer(trigger: Trigger<Pointer<Over>>, mut hovered: ResMut<HoveredMonument>) {
+    hovered.0 = Some(trigger.entity());
+}
+
+fn monument_left_observer(trigger: Trigger<Pointer<Out>>, mut hovered: ResMut<HoveredMonument>) {
+    if hovered.0 == Some(trigger.entity()) {
+        hovered.0 = None;
+    }
+}
+
+fn add_monument_tooltip_system(mut commands: Commands) {
+    commands.spawn((
+        MonumentTooltip,
+        Text::new(""),
+        TextFont::default().with_font_size(14.0),
+        TextColor(GRAY_800.into()),
+        Node {
+            position_type: PositionType::Absolute,
+            max_width: Val::Px(280.0),
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        BorderRadius::all(Val::Px(8.0)),
+        BackgroundColor(Color::srgba(1.0, 1.0, 1.0, 0.9)),
+        Visibility::Hidden,
     ));
+}
+
+/// Description, author and age of the hovered monument, next to the cursor
+fn update_monument_tooltip_system(
+    hovered: Res<HoveredMonument>,
+    monuments: Query<&Monument>,
+    windows: Query<&Window>,
+    mut tooltip: Query<(&mut Text, &mut Node, &mut Visibility), With<MonumentTooltip>>,
+) {
+    let Ok((mut text, mut node, mut visibility)) = tooltip.get_single_mut() else {
+        return;
+    };
+
+    let monument = hovered.0.and_then(|entity| monuments.get(entity).ok());
+    let cursor = windows.get_single().ok().and_then(|window| window.cursor_position());
+
+    let (Some(monument), Some(cursor)) = (monument, cursor) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    let author = match monument.owner_name.as_str() {
+        "" => "an unknown builder".to_string(),
+        name => name.to_string(),
+    };
+
+    text.0 = match monument.created_at {
+        0 => format!("{}\nby {}", monument.description, author),
+        created_at => format!("{}\nby {}, {}", monument.description, author, age(created_at)),
+    };
+
+    node.left = Val::Px(cursor.x + 16.0);
+    node.top = Val::Px(cursor.y + 16.0);
+    *visibility = Visibility::Visible;
+}
+
+/// How long ago the Unix timestamp was, in words
+fn age(created_at: u64) -> String {
+    let now = (js_sys::Date::now() / 1000.0) as u64;
+    let seconds = now.saturating_sub(created_at);
+
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => plural(seconds / 60, "minute"),
+        3600..86400 => plural(seconds / 3600, "hour"),
+        _ => plural(seconds / 86400, "day"),
+    }
+}
 
+fn plural(amount: u64, unit: &str) -> String {
+    if amount == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", amount, unit)
+    }
 }
 
 fn animate_monument_system(
@@ -0,0 +1,118 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::color::palettes::tailwind::{GRAY_800, SKY_100};
+use bevy::prelude::*;
+
+use shared::{ChatChannel, SystemMessages};
+
+use crate::js_bridge_plugin::{show_chat, JSBridgeMessages, JsBridgeMessageReceived, SendJsBridgeMessage};
+use crate::network::{SendWebSocketMessage, WebSocketMessageReceived};
+use crate::robot::{FollowRobot, PlayerKind, NAME_TAG_HEIGHT};
+
+/// How long a speech bubble stays above a robot
+const BUBBLE_DURATION: f32 = 6.0;
+const BUBBLE_WIDTH: f32 = 220.0;
+
+pub struct ChatPlugin;
+
+#[derive(Component)]
+struct SpeechBubble {
+    robot: Entity,
+    timer: Timer,
+}
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, send_chat_system);
+        app.add_systems(Update, listen_for_chat_system);
+        app.add_systems(Update, expire_speech_bubbles_system);
+    }
+}
+
+/// The chat box is armed once the main player spawns and re-armed after every message
+fn send_chat_system(
+    mut server_events: EventReader<WebSocketMessageReceived>,
+    mut js_bridge_events: EventReader<JsBridgeMessageReceived>,
+    mut js_bridge: EventWriter<SendJsBridgeMessage>,
+    mut websocket: EventWriter<SendWebSocketMessage>,
+) {
+    for event in server_events.read() {
+        if let SystemMessages::MainPlayerSpawn { .. } = event.0 {
+            js_bridge.send(SendJsBridgeMessage(JSBridgeMessages::CallWaitForChat));
+        }
+    }
+
+    for event in js_bridge_events.read() {
+        if let JSBridgeMessages::CallWaitForChatResponse(response) = &event.0 {
+            if let Some((channel, text)) = response {
+                websocket.send(SendWebSocketMessage(SystemMessages::SendChat { channel: *channel, text: text.clone() }));
+            }
+
+            js_bridge.send(SendJsBridgeMessage(JSBridgeMessages::CallWaitForChat));
+        }
+    }
+}
+
+fn listen_for_chat_system(
+    mut commands: Commands,
+    robots: Query<(Entity, &PlayerKind)>,
+    bubbles: Query<(Entity, &SpeechBubble)>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        let SystemMessages::Chat { id, name, channel, text } = &event.0 else {
+            continue;
+        };
+
+        show_chat(name.clone(), format!("{:?}", channel), text.clone());
+
+        let Some((robot, _)) = robots.iter().find(|(_, kind)| kind.data().id == *id) else {
+            continue;
+        };
+
+        // A robot only says one thing at a time
+        for (entity, bubble) in &bubbles {
+            if bubble.robot == robot {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        let background = match channel {
+            ChatChannel::Global => Color::WHITE,
+            ChatChannel::Proximity => SKY_100.into(),
+        };
+
+        commands
+            .spawn((
+                SpeechBubble { robot, timer: Timer::from_seconds(BUBBLE_DURATION, TimerMode::Once) },
+                FollowRobot { robot, height: NAME_TAG_HEIGHT + 0.8, width: BUBBLE_WIDTH },
+                Node {
+                    position_type: PositionType::Absolute,
+                    width: Val::Px(BUBBLE_WIDTH),
+                    padding: UiRect::axes(Val::Px(10.0), Val::Px(6.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                BorderRadius::all(Val::Px(12.0)),
+                BackgroundColor(background),
+                Visibility::Hidden,
+            ))
+            .with_child((
+                Text::new(text.clone()),
+                TextFont::default().with_font_size(14.0),
+                TextColor(GRAY_800.into()),
+                TextLayout::new_with_justify(JustifyText::Center),
+            ));
+    }
+}
+
+fn expire_speech_bubbles_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut bubbles: Query<(Entity, &mut SpeechBubble)>,
+) {
+    for (entity, mut bubble) in &mut bubbles {
+        if bubble.timer.tick(time.delta()).just_finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::gltf::GltfMaterialName;
+use bevy::prelude::*;
+
+use shared::{Accessory, RobotColor, SystemMessages};
+
+use crate::js_bridge_plugin::{JSBridgeMessages, JsBridgeMessageReceived};
+use crate::network::{SendWebSocketMessage, WebSocketMessageReceived};
+use crate::robot::PlayerKind;
+
+/// Material of `RobotExpressive.glb` covering the body, the grey and black parts are left alone
+const BODY_MATERIAL: &str = "Main";
+
+/// Top of the robot's head, in model space
+const HEAD_HEIGHT: f32 = 2.9;
+
+pub struct CosmeticsPlugin;
+
+/// Accessory worn by a robot, spawned as one of its children
+#[derive(Component)]
+pub struct WornAccessory;
+
+impl Plugin for CosmeticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, buy_cosmetics_system);
+        app.add_systems(Update, listen_for_cosmetics_changed_system);
+    }
+}
+
+fn buy_cosmetics_system(
+    mut websocket: EventWriter<SendWebSocketMessage>,
+    mut js_bridge_events: EventReader<JsBridgeMessageReceived>,
+) {
+    for js_bridge_event in js_bridge_events.read() {
+        if let JSBridgeMessages::CallOpenShopResponse(Some(cosmetics)) = &js_bridge_event.0 {
+            websocket.send(SendWebSocketMessage(SystemMessages::ChangeCosmetics { cosmetics: *cosmetics }));
+        }
+    }
+}
+
+fn listen_for_cosmetics_changed_system(
+    mut commands: Commands,
+    mut robots: Query<(Entity, &mut PlayerKind)>,
+    children: Query<&Children>,
+    mut parts: Query<(&mut MeshMaterial3d<StandardMaterial>, &GltfMaterialName)>,
+    worn: Query<(Entity, &Parent), With<WornAccessory>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        if let SystemMessages::PlayerCosmeticsChanged { id, cosmetics } = &event.0 {
+            for (entity, mut kind) in &mut robots {
+                if kind.data().id != *id {
+                    continue;
+                }
+
+                // Robots whose scene isn't ready yet pick it up from their data once it is
+                kind.data_mut().cosmetics = *cosmetics;
+
+                tint_robot(entity, cosmetics.color, &children, &mut parts, &mut materials);
+
+                for (accessory, parent) in &worn {
+                    if parent.get() == entity {
+                        commands.entity(accessory).despawn_recursive();
+                    }
+                }
+
+                equip_accessory(&mut commands, entity, cosmetics.accessory, &mut meshes, &mut materials);
+            }
+        }
+    }
+}
+
+/// Paint the body of an already loaded robot scene
+pub fn tint_robot(
+    robot: Entity,
+    color: RobotColor,
+    children: &Query<&Children>,
+    parts: &mut Query<(&mut MeshMaterial3d<StandardMaterial>, &GltfMaterialName)>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    let [red, green, blue] = color.rgb();
+
+    for child in children.iter_descendants(robot) {
+        let Ok((mut material, name)) = parts.get_mut(child) else {
+            continue;
+        };
+
+        if name.0 != BODY_MATERIAL {
+            continue;
+        }
+
+        // The material is shared by every robot, each one gets its own copy
+        let Some(mut tinted) = materials.get(&material.0).cloned() else {
+            continue;
+        };
+
+        tinted.base_color = Color::linear_rgb(red, green, blue);
+        material.0 = materials.add(tinted);
+    }
+}
+
+pub fn equip_accessory(
+    commands: &mut Commands,
+    robot: Entity,
+    accessory: Accessory,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    let parts = match accessory {
+        Accessory::None => return,
+        Accessory::TopHat => {
+            let black = materials.add(StandardMaterial {
+                base_color: Color::srgb(0.05, 0.05, 0.05),
+                perceptual_roughness: 0.6,
+                ..default()
+            });
+
+            vec![
+                (meshes.add(Cylinder::new(0.55, 0.05)), black.clone(), Vec3::Y * HEAD_HEIGHT),
+                (meshes.add(Cylinder::new(0.35, 0.6)), black, Vec3::Y * (HEAD_HEIGHT + 0.3)),
+            ]
+        }
+        Accessory::Crown => {
+            let gold = materials.add(StandardMaterial {
+                base_color: Color::srgb(1.0, 0.78, 0.1),
+                metallic: 1.0,
+                perceptual_roughness: 0.3,
+                ..default()
+            });
+
+            vec![(meshes.add(Cylinder::new(0.4, 0.3)), gold, Vec3::Y * (HEAD_HEIGHT + 0.15))]
+        }
+        Accessory::Halo => {
+            let light = materials.add(StandardMaterial {
+                base_color: Color::srgb(1.0, 0.95, 0.6),
+                emissive: LinearRgba::rgb(4.0, 3.6, 1.8),
+                ..default()
+            });
+
+            vec![(meshes.add(Torus::new(0.3, 0.4)), light, Vec3::Y * (HEAD_HEIGHT + 0.6))]
+        }
+    };
+
+    commands.entity(robot).with_children(|parent| {
+        for (mesh, material, translation) in parts {
+            parent.spawn((
+                WornAccessory,
+                Mesh3d(mesh),
+                MeshMaterial3d(material),
+                Transform::from_translation(translation),
+            ));
+        }
+    });
+}
@@ -4,9 +4,10 @@ use bevy::app::{App, Plugin, Update};
 use bevy::math::Vec2;
 use bevy::prelude::*;
 use bevy_sprite3d::{Sprite3dBuilder, Sprite3dParams};
-use shared::SystemMessages;
+use shared::{Accessory, ChatChannel, Cosmetics, RobotColor, SystemMessages};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::{spawn_local, JsFuture};
 
 pub struct JsBridgePlugin;
@@ -14,6 +15,10 @@ pub struct JsBridgePlugin;
 #[wasm_bindgen]
 extern "C" {
     pub fn show_modal() -> js_sys::Promise;
+    pub fn ask_name(reason: Option<String>) -> js_sys::Promise;
+    pub fn show_shop(balance: u32, current: JsValue, catalog: JsValue) -> js_sys::Promise;
+    pub fn wait_for_chat() -> js_sys::Promise;
+    pub fn show_chat(name: String, channel: String, text: String);
 }
 
 pub async fn call_show_modal() -> JSBridgeMessages {
@@ -22,10 +27,93 @@ pub async fn call_show_modal() -> JSBridgeMessages {
     )
 }
 
+pub async fn call_ask_name(reason: Option<String>) -> JSBridgeMessages {
+    JSBridgeMessages::CallAskNameResponse(
+        JsFuture::from(ask_name(reason)).await.ok().map(|value| value.as_string()).flatten()
+    )
+}
+
+/// The shop gets `{ color, accessory }` as the current loadout and a catalog of `{ name, price }`
+/// for each part, then resolves with the loadout to buy or `null`
+pub async fn call_show_shop(balance: u32, current: Cosmetics) -> JSBridgeMessages {
+    let catalog = js_sys::Object::new();
+
+    set(&catalog, "colors", items(RobotColor::ALL.iter().map(|color| (format!("{:?}", color), color.price()))));
+    set(&catalog, "accessories", items(Accessory::ALL.iter().map(|accessory| (format!("{:?}", accessory), accessory.price()))));
+
+    let response = JsFuture::from(show_shop(balance, loadout(&current), catalog.into())).await.ok();
+
+    JSBridgeMessages::CallOpenShopResponse(
+        response.filter(|value| value.is_object()).and_then(|value| {
+            let color = get(&value, "color")?;
+            let accessory = get(&value, "accessory")?;
+
+            Some(Cosmetics {
+                color: *RobotColor::ALL.iter().find(|candidate| format!("{:?}", candidate) == color)?,
+                accessory: *Accessory::ALL.iter().find(|candidate| format!("{:?}", candidate) == accessory)?,
+            })
+        })
+    )
+}
+
+/// Resolves with `{ channel, text }` once the player sends a chat message
+pub async fn call_wait_for_chat() -> JSBridgeMessages {
+    let response = JsFuture::from(wait_for_chat()).await.ok();
+
+    JSBridgeMessages::CallWaitForChatResponse(
+        response.filter(|value| value.is_object()).and_then(|value| {
+            let channel = match get(&value, "channel")?.as_str() {
+                "Proximity" => ChatChannel::Proximity,
+                _ => ChatChannel::Global,
+            };
+
+            Some((channel, get(&value, "text")?))
+        })
+    )
+}
+
+fn loadout(cosmetics: &Cosmetics) -> JsValue {
+    let loadout = js_sys::Object::new();
+
+    set(&loadout, "color", format!("{:?}", cosmetics.color).into());
+    set(&loadout, "accessory", format!("{:?}", cosmetics.accessory).into());
+
+    loadout.into()
+}
+
+fn items(items: impl Iterator<Item=(String, u32)>) -> JsValue {
+    items
+        .map(|(name, price)| {
+            let item = js_sys::Object::new();
+            set(&item, "name", name.into());
+            set(&item, "price", price.into());
+            JsValue::from(item)
+        })
+        .collect::<js_sys::Array>()
+        .into()
+}
+
+fn set(object: &js_sys::Object, key: &str, value: JsValue) {
+    let _ = js_sys::Reflect::set(object, &key.into(), &value);
+}
+
+fn get(object: &JsValue, key: &str) -> Option<String> {
+    js_sys::Reflect::get(object, &key.into()).ok()?.as_string()
+}
+
 #[derive(Debug, Clone)]
 pub enum JSBridgeMessages {
     CallOpenModal,
     CallOpenModalResponse(Option<String>),
+    /// Ask the player for a display name, with the reason the previous one was refused
+    CallAskName(Option<String>),
+    CallAskNameResponse(Option<String>),
+    /// Open the shop with the player's balance and current loadout
+    CallOpenShop(u32, Cosmetics),
+    CallOpenShopResponse(Option<Cosmetics>),
+    /// Wait for the player to type something in the chat box
+    CallWaitForChat,
+    CallWaitForChatResponse(Option<(ChatChannel, String)>),
     None,
 }
 
@@ -65,6 +153,9 @@ impl Plugin for JsBridgePlugin {
                 spawn_local(async move {
                     let response: JSBridgeMessages = match message {
                         JSBridgeMessages::CallOpenModal => call_show_modal().await,
+                        JSBridgeMessages::CallAskName(reason) => call_ask_name(reason).await,
+                        JSBridgeMessages::CallOpenShop(balance, current) => call_show_shop(balance, current).await,
+                        JSBridgeMessages::CallWaitForChat => call_wait_for_chat().await,
                         _ => JSBridgeMessages::None
                     };
 
@@ -1,8 +1,11 @@
 #![allow(warnings)]
 use crate::builder::BuilderPlugin;
+use crate::chat::ChatPlugin;
+use crate::cosmetics::CosmeticsPlugin;
 use crate::js_bridge_plugin::JsBridgePlugin;
 use crate::camera::CameraController;
 use crate::network::NetworkPlugin;
+use crate::physics::PhysicsPlugin;
 use crate::robot::RobotPlugin;
 use crate::tokens::TokensPlugin;
 use crate::ui::UIPlugin;
@@ -30,6 +33,9 @@ mod builder;
 mod ui;
 mod camera;
 mod sound_effects;
+mod cosmetics;
+mod chat;
+mod physics;
 
 #[wasm_bindgen]
 pub fn start_application(canvas: Option<String>) {
@@ -41,6 +47,9 @@ pub fn start_application(canvas: Option<String>) {
             TokensPlugin,
             UIPlugin,
             BuilderPlugin,
+            CosmeticsPlugin,
+            ChatPlugin,
+            PhysicsPlugin,
         ))
         .add_plugins((
             WebAssetPlugin::default(),
@@ -1,15 +1,4 @@
-#![allow(warnings)]
-mod js_bridge_plugin;
-mod mouse_plugin;
-mod network;
-mod robot;
-mod tokens;
-mod builder;
-mod ui;
-mod sound_effects;
-
-use bevy::prelude::*;
-use num_traits::{Float, FloatConst};
-use std::any::Any;
-
-fn main() {}
+// Every module lives in the `game` library, the browser starts it through `start_application`
+fn main() {
+    game::start_application(None);
+}
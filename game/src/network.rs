@@ -3,13 +3,44 @@ use bincode::config::standard;
 use futures_util::{SinkExt, StreamExt};
 use gloo_timers::future::TimeoutFuture;
 use std::env;
+use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio_tungstenite_wasm::Message;
 
 use shared::SystemMessages;
 
 #[derive(Resource)]
-pub struct WebSocketReceiver(pub UnboundedReceiver<SystemMessages>);
+pub struct WebSocketReceiver(UnboundedReceiver<Downstream>);
+
+/// What the connection task reports back, in order
+enum Downstream {
+    Message(SystemMessages),
+    State(ConnectionState),
+}
+
+/// Asks the connection task to act on the current connection
+#[derive(Debug)]
+enum Control {
+    /// Drop the connection and start over
+    Reconnect,
+}
+
+#[derive(Resource, Clone)]
+struct ConnectionControl(UnboundedSender<Control>);
+
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Connected,
+    Reconnecting { attempt: u32 },
+    Failed,
+}
+
+/// Fired once the connection is back after being lost, the world is about to be synced all over
+/// again, starting with a new main player
+#[derive(Event, Debug, Clone)]
+pub struct Reconnected;
 
 #[derive(Resource, Clone)]
 pub struct WebSocketSender(pub UnboundedSender<SystemMessages>);
@@ -20,15 +51,63 @@ pub struct WebSocketMessageReceived(pub SystemMessages);
 #[derive(Event, Debug)]
 pub struct SendWebSocketMessage(pub SystemMessages);
 
+/// Fired once the server stops answering our heartbeat pings
+#[derive(Event, Debug, Clone)]
+pub struct ServerUnresponsive;
+
+/// How often the client pings the server
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the server has to answer a ping before it is considered gone
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Wait before the first reconnection attempt, doubled after every failed one
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Attempts in a row before giving up on the server
+const MAX_RECONNECT_ATTEMPTS: u32 = 12;
+
+#[derive(Resource)]
+struct Heartbeat {
+    timer: Timer,
+    /// When the oldest unanswered ping was sent
+    awaiting_since: Option<Duration>,
+    unresponsive: bool,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(HEARTBEAT_INTERVAL, TimerMode::Repeating),
+            awaiting_since: None,
+            unresponsive: false,
+        }
+    }
+}
+
 pub struct NetworkPlugin;
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut App) {
-        let (downstream_sender, downstream_receiver) = unbounded_channel::<SystemMessages>();
-        let (upstream_sender, mut upstream_receiver) = unbounded_channel::<SystemMessages>();
+        let (downstream_sender, downstream_receiver) = unbounded_channel::<Downstream>();
+        let (upstream_sender, upstream_receiver) = unbounded_channel::<SystemMessages>();
+        let (control_sender, control_receiver) = unbounded_channel::<Control>();
 
         app.add_systems(Update, debug_websocket_messages_system);
         app.add_systems(Update, send_ping_system);
+        app.add_systems(Update, server_closing_connection_system);
+
+        // Heartbeat
+        app.add_event::<ServerUnresponsive>();
+        app.init_resource::<Heartbeat>();
+        app.add_systems(Update, heartbeat_system.after(websocket_event_bridge_system));
+
+        // Connection
+        app.add_event::<Reconnected>();
+        app.init_resource::<ConnectionState>();
+        app.insert_resource(ConnectionControl(control_sender));
+        app.add_systems(Update, reconnect_unresponsive_server_system.after(heartbeat_system));
 
         // Server to Client
         app.add_event::<WebSocketMessageReceived>();
@@ -42,35 +121,113 @@ impl Plugin for NetworkPlugin {
 
         wasm_bindgen_futures::spawn_local(async move {
             let websocket_server_address = env!("WEBSOCKET_SERVER_ADDRESS");
-
             let url = url::Url::parse(&websocket_server_address).unwrap();
-            let mut stream = tokio_tungstenite_wasm::connect(url)
-                .await
-                .expect("failed to connect");
 
-            let (mut write, mut read) = stream.split();
+            maintain_connection(url, upstream_receiver, downstream_sender, control_receiver).await;
+        });
+    }
+}
 
-            wasm_bindgen_futures::spawn_local(async move {
-                while let Some(message) = upstream_receiver.recv().await {
-                    if let Err(error) = write.send(message.into()).await {
-                        error!("failed to send: {:?}", error);
-                    }
-                }
-            });
+/// Keep a connection to the server for as long as the game runs, reconnecting with an exponential
+/// backoff whenever it drops. Nothing the server closes connections for is final, evicted players
+/// come back like everyone else and only give up once the server stayed out of reach for too long
+async fn maintain_connection(
+    url: url::Url,
+    mut upstream: UnboundedReceiver<SystemMessages>,
+    downstream: UnboundedSender<Downstream>,
+    mut control: UnboundedReceiver<Control>,
+) {
+    let mut attempt = 0;
+
+    loop {
+        let mut hold_off = Duration::ZERO;
+
+        // Whatever was sent while offline belongs to the previous session
+        while upstream.try_recv().is_ok() {}
+
+        match tokio_tungstenite_wasm::connect(url.clone()).await {
+            Ok(stream) => {
+                let _ = downstream.send(Downstream::State(ConnectionState::Connected));
+
+                let (mut write, mut read) = stream.split();
+
+                loop {
+                    tokio::select! {
+                        message = upstream.recv() => {
+                            let Some(message) = message else {
+                                return;
+                            };
+
+                            if let Err(error) = write.send(message.into()).await {
+                                error!("failed to send: {:?}", error);
+                                break;
+                            }
+                        }
+                        message = read.next() => {
+                            let Some(Ok(message)) = message else {
+                                warn!("connection to the server lost");
+                                break;
+                            };
+
+                            let Ok(message) = SystemMessages::try_from(message) else {
+                                continue;
+                            };
 
-            wasm_bindgen_futures::spawn_local(async move {
-                while let Some(Ok(message)) = read.next().await {
-                    if let Ok(message) = message.try_into() {
-                        if let Err(error) = downstream_sender.send(message) {
-                            error!("failed to send message {:?}", error)
+                            // Read here rather than by a system, the socket may well be closed by then
+                            match &message {
+                                SystemMessages::ServerShuttingDown { reconnect_after } => {
+                                    hold_off = hold_off.max(Duration::from_secs(*reconnect_after as u64));
+                                }
+                                // Only making it into the world resets the backoff, players turned
+                                // away keep waiting longer between attempts
+                                SystemMessages::MainPlayerSpawn { .. } => attempt = 0,
+                                _ => {}
+                            }
+
+                            if downstream.send(Downstream::Message(message)).is_err() {
+                                return;
+                            }
+                        }
+                        command = control.recv() => {
+                            match command {
+                                Some(Control::Reconnect) => break,
+                                None => return,
+                            }
                         }
                     }
                 }
-            });
-        });
+
+                let _ = write.close().await;
+            }
+            Err(error) => error!("failed to connect: {:?}", error),
+        }
+
+        // Reconnecting anyway
+        while control.try_recv().is_ok() {}
+
+        attempt += 1;
+
+        if attempt > MAX_RECONNECT_ATTEMPTS {
+            let _ = downstream.send(Downstream::State(ConnectionState::Failed));
+            return;
+        }
+
+        let _ = downstream.send(Downstream::State(ConnectionState::Reconnecting { attempt }));
+
+        let wait = backoff(attempt).max(hold_off);
+        TimeoutFuture::new(wait.as_millis() as u32).await;
     }
 }
 
+/// Doubles with every attempt, with some jitter so clients dropped together don't all come back
+/// at once
+fn backoff(attempt: u32) -> Duration {
+    let exponential = INITIAL_BACKOFF.saturating_mul(1u32 << attempt.saturating_sub(1).min(16));
+    let wait = exponential.min(MAX_BACKOFF);
+
+    wait.mul_f64(0.75 + fastrand::f64() * 0.5)
+}
+
 /// Send a ping to the server whenever the pressing the letter P (for debugging purpose)
 /// Server will reply with a pong
 fn send_ping_system(keyboard: Res<ButtonInput<KeyCode>>, mut event: EventWriter<SendWebSocketMessage>) {
@@ -79,6 +236,60 @@ fn send_ping_system(keyboard: Res<ButtonInput<KeyCode>>, mut event: EventWriter<
     }
 }
 
+/// Ping the server periodically and flag it as unresponsive when nothing comes back in time,
+/// the timeout is measured from the oldest unanswered ping so a frozen (background) tab doesn't count
+fn heartbeat_system(
+    time: Res<Time>,
+    state: Res<ConnectionState>,
+    mut heartbeat: ResMut<Heartbeat>,
+    mut events: EventReader<WebSocketMessageReceived>,
+    mut websocket: EventWriter<SendWebSocketMessage>,
+    mut unresponsive: EventWriter<ServerUnresponsive>,
+) {
+    let now = time.elapsed();
+
+    if *state != ConnectionState::Connected {
+        return;
+    }
+
+    if events.read().count() > 0 {
+        heartbeat.awaiting_since = None;
+        heartbeat.unresponsive = false;
+    }
+
+    if heartbeat.timer.tick(time.delta()).just_finished() {
+        websocket.send(SendWebSocketMessage(SystemMessages::Ping));
+        heartbeat.awaiting_since.get_or_insert(now);
+    }
+
+    if let Some(since) = heartbeat.awaiting_since {
+        if heartbeat.unresponsive == false && now - since > HEARTBEAT_TIMEOUT {
+            heartbeat.unresponsive = true;
+            error!("server hasn't answered for {:?}", now - since);
+            unresponsive.send(ServerUnresponsive);
+        }
+    }
+}
+
+/// The server announces when it is about to close the connection, the connection task already
+/// knows when to come back
+fn server_closing_connection_system(mut events: EventReader<WebSocketMessageReceived>) {
+    for event in events.read() {
+        match &event.0 {
+            SystemMessages::ServerShuttingDown { reconnect_after } => {
+                warn!("server is shutting down, reconnect in {}s", reconnect_after);
+            }
+            SystemMessages::Evicted { reason } => {
+                warn!("disconnected by the server: {}", reason);
+            }
+            SystemMessages::RateLimited { kind, retry_after_ms } => {
+                warn!("server is dropping {} messages, retry in {}ms", kind, retry_after_ms);
+            }
+            _ => continue
+        }
+    }
+}
+
 fn debug_websocket_messages_system(mut events: EventReader<WebSocketMessageReceived>) {
     for event in events.read() {
         info!("received: {:?}", event.0);
@@ -93,8 +304,40 @@ fn websocket_send_event_system(mut events: EventReader<SendWebSocketMessage>, se
     }
 }
 
-fn websocket_event_bridge_system(mut receiver: ResMut<WebSocketReceiver>, mut writer: EventWriter<WebSocketMessageReceived>) {
-    while let Ok(message) = receiver.0.try_recv() {
-        writer.send(WebSocketMessageReceived(message));
+/// Messages following a change of state are held until the next frame, so that everything
+/// reacting to the change is done before the new session's messages come in
+fn websocket_event_bridge_system(
+    mut receiver: ResMut<WebSocketReceiver>,
+    mut writer: EventWriter<WebSocketMessageReceived>,
+    mut state: ResMut<ConnectionState>,
+    mut heartbeat: ResMut<Heartbeat>,
+    mut reconnected: EventWriter<Reconnected>,
+) {
+    while let Ok(downstream) = receiver.0.try_recv() {
+        match downstream {
+            Downstream::Message(message) => {
+                writer.send(WebSocketMessageReceived(message));
+            }
+            Downstream::State(next) => {
+                info!("connection: {:?} -> {:?}", *state, next);
+
+                if next == ConnectionState::Connected {
+                    *heartbeat = Heartbeat::default();
+
+                    if *state != ConnectionState::Connecting {
+                        reconnected.send(Reconnected);
+                    }
+                }
+
+                *state = next;
+                return;
+            }
+        }
+    }
+}
+
+fn reconnect_unresponsive_server_system(mut events: EventReader<ServerUnresponsive>, control: Res<ConnectionControl>) {
+    for _ in events.read() {
+        let _ = control.0.send(Control::Reconnect);
     }
 }
@@ -0,0 +1,204 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use shared::Monument;
+
+use crate::robot::Robot;
+
+/// Radius of a robot's body on the ground
+const ROBOT_RADIUS: f32 = 0.5;
+
+/// Monuments are flat sprites, their bodies get this much depth on the ground
+const MONUMENT_DEPTH: f32 = 0.6;
+
+/// How far up from their lowest opaque row the base of a sprite goes, in pixels
+const BASE_HEIGHT: u32 = 40;
+
+/// Pixels with less alpha than this are see-through
+const OPAQUE_ALPHA: f32 = 0.5;
+
+/// Kept between bodies so a robot never ends up touching, and stuck in, an obstacle
+const SKIN: f32 = 0.02;
+
+/// Remainder of a blocked step is slid along the obstacle at most this many times
+const MAX_SLIDES: usize = 2;
+
+/// Robots walk through each other, only monuments stand in their way
+const ROBOTS: Group = Group::GROUP_1;
+const MONUMENTS: Group = Group::GROUP_2;
+
+pub struct PhysicsPlugin;
+
+/// Collider standing in for the entity on the ground plane. Physics are 2D, the world's X and Z
+/// axes are the X and Y axes of the colliders, which live on their own entities
+#[derive(Component)]
+pub struct Footprint(pub Entity);
+
+#[derive(Component)]
+struct FootprintOf(Entity);
+
+/// Image of a monument's sprite, its opaque base is what robots bump into
+#[derive(Component)]
+pub struct SpriteImage(pub Handle<Image>);
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.0));
+        app.add_systems(Update, add_robot_footprints_system);
+        app.add_systems(Update, add_monument_footprints_system);
+        app.add_systems(Update, follow_robots_system);
+        app.add_systems(Update, remove_orphan_footprints_system);
+    }
+}
+
+fn ground(translation: Vec3) -> Vec2 {
+    Vec2::new(translation.x, translation.z)
+}
+
+fn add_robot_footprints_system(mut commands: Commands, robots: Query<(Entity, &Transform), Added<Robot>>) {
+    for (robot, transform) in &robots {
+        let footprint = commands
+            .spawn((
+                FootprintOf(robot),
+                Collider::ball(ROBOT_RADIUS),
+                CollisionGroups::new(ROBOTS, Group::ALL),
+                Transform::from_translation(ground(transform.translation).extend(0.0)),
+            ))
+            .id();
+
+        commands.entity(robot).insert(Footprint(footprint));
+    }
+}
+
+fn follow_robots_system(
+    robots: Query<(&Transform, &Footprint), (With<Robot>, Changed<Transform>)>,
+    mut footprints: Query<&mut Transform, (With<FootprintOf>, Without<Robot>)>,
+) {
+    for (transform, footprint) in &robots {
+        if let Ok(mut footprint) = footprints.get_mut(footprint.0) {
+            footprint.translation = ground(transform.translation).extend(0.0);
+        }
+    }
+}
+
+/// Sprites are swapped once a monument is completed, the body follows its new image
+fn add_monument_footprints_system(
+    mut commands: Commands,
+    monuments: Query<(Entity, &Monument, &Transform, &SpriteImage, Option<&Footprint>), Changed<SpriteImage>>,
+    images: Res<Assets<Image>>,
+) {
+    for (entity, monument, transform, image, previous) in &monuments {
+        if let Some(previous) = previous {
+            commands.entity(previous.0).despawn();
+        }
+
+        let Some(image) = images.get(&image.0) else {
+            continue;
+        };
+
+        let Some((left, right)) = opaque_base(image) else {
+            continue;
+        };
+
+        // Sprites are pivoted on the middle of their bottom edge
+        let metres_per_pixel = transform.scale.x / 100.0;
+        let half_width = (right - left) as f32 / 2.0 * metres_per_pixel;
+        let offset = ((left + right) as f32 / 2.0 - image.width() as f32 / 2.0) * metres_per_pixel;
+
+        // Turning around Y by an angle turns the ground plane the other way
+        let (axis, angle) = transform.rotation.to_axis_angle();
+        let angle = if axis.y < 0.0 { angle } else { -angle };
+        let along = Vec2::from_angle(angle);
+
+        let footprint = commands
+            .spawn((
+                FootprintOf(entity),
+                Collider::cuboid(half_width.max(SKIN), MONUMENT_DEPTH / 2.0),
+                CollisionGroups::new(MONUMENTS, Group::ALL),
+                Transform {
+                    translation: (ground(monument.position.to_vec3()) + along * offset).extend(0.0),
+                    rotation: Quat::from_rotation_z(angle),
+                    ..default()
+                },
+            ))
+            .id();
+
+        commands.entity(entity).insert(Footprint(footprint));
+    }
+}
+
+/// Leftmost and rightmost opaque columns within the base of the image, which stands on the ground
+fn opaque_base(image: &Image) -> Option<(u32, u32)> {
+    let (width, height) = (image.width(), image.height());
+
+    let is_opaque = |x: u32, y: u32| image.get_color_at(x, y).is_ok_and(|color| color.alpha() >= OPAQUE_ALPHA);
+
+    let bottom = (0..height).rev().find(|y| (0..width).any(|x| is_opaque(x, *y)))?;
+    let top = bottom.saturating_sub(BASE_HEIGHT);
+
+    let mut columns = (0..width).filter(|x| (top..=bottom).any(|y| is_opaque(*x, y)));
+    let left = columns.next()?;
+    let right = columns.last().unwrap_or(left);
+
+    Some((left, right + 1))
+}
+
+fn remove_orphan_footprints_system(
+    mut commands: Commands,
+    footprints: Query<(Entity, &FootprintOf)>,
+    owners: Query<(), With<Footprint>>,
+) {
+    for (entity, owner) in &footprints {
+        if owners.contains(owner.0) == false {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// How much of `step` a robot can walk before bumping into a monument, the rest of it slides along
+/// the monument it bumped into
+pub fn resolve_movement(context: &RapierContext, from: Vec3, step: Vec3, footprint: Option<&Footprint>) -> Vec3 {
+    let mut filter = QueryFilter::default().groups(CollisionGroups::new(Group::ALL, MONUMENTS));
+
+    if let Some(footprint) = footprint {
+        filter = filter.exclude_collider(footprint.0);
+    }
+
+    let shape = Collider::ball(ROBOT_RADIUS);
+    let options = ShapeCastOptions {
+        max_time_of_impact: 1.0,
+        target_distance: SKIN,
+        stop_at_penetration: false,
+        compute_impact_geometry_on_penetration: true,
+    };
+
+    let mut position = ground(from);
+    let mut remaining = ground(step);
+
+    for _ in 0..=MAX_SLIDES {
+        if remaining.length_squared() < f32::EPSILON {
+            break;
+        }
+
+        let Some((_, hit)) = context.cast_shape(position, 0.0, remaining, &shape, options, filter) else {
+            position += remaining;
+            break;
+        };
+
+        position += remaining * hit.time_of_impact;
+
+        let Some(details) = hit.details else {
+            break;
+        };
+
+        // Keep going along the obstacle, away from it
+        let normal = details.normal1;
+        let left = remaining * (1.0 - hit.time_of_impact);
+
+        remaining = left - normal * left.dot(normal);
+    }
+
+    let moved = position - ground(from);
+    Vec3::new(moved.x, 0.0, moved.y)
+}
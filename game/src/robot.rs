@@ -1,10 +1,12 @@
+use std::collections::{HashSet, VecDeque};
 use std::time::Duration;
 
 use bevy::animation::{AnimationClip, AnimationPlayer};
 use bevy::app::{App, Startup, Update};
 use bevy::asset::{AssetPath, AssetServer, Assets, Handle};
+use bevy::color::palettes::tailwind::GRAY_800;
 use bevy::ecs::bundle::DynamicBundle;
-use bevy::gltf::GltfAssetLabel;
+use bevy::gltf::{GltfAssetLabel, GltfMaterialName};
 use bevy::input::ButtonInput;
 use bevy::log::info;
 use bevy::math::{Quat, Vec3};
@@ -12,18 +14,24 @@ use bevy::pbr::StandardMaterial;
 use bevy::prelude::*;
 use bevy::scene::SceneInstanceReady;
 use bevy_kira_audio::{Audio, AudioControl};
-use bevy_rapier2d::geometry::Collider;
-use bevy_sprite3d::Sprite3d;
+use bevy_rapier2d::prelude::ReadRapierContext;
 
-use shared::{PlayerData, PlayerId, SystemMessages};
+use shared::pathfinding::find_path;
+use shared::{Coordinate, Cosmetics, EmoteKind, Monument, PlayerData, PlayerId, SystemMessages, MAX_MOVE_DISTANCE, ROBOT_SPEED};
 
-use crate::network::{SendWebSocketMessage, WebSocketMessageReceived};
+use crate::cosmetics::{equip_accessory, tint_robot};
+use crate::js_bridge_plugin::{JSBridgeMessages, JsBridgeMessageReceived, SendJsBridgeMessage};
+use crate::network::{Reconnected, SendWebSocketMessage, WebSocketMessageReceived};
+use crate::physics::{resolve_movement, Footprint};
 use crate::sound_effects::AudioCache;
-use crate::tokens::Token;
 use crate::ui::{handle_build_monument_button_state_system, UiInputBlocker};
 
 pub const ROBOT_GLB_PATH: &str = "RobotExpressive.glb";
 
+/// How high above the robot's feet its name is shown
+pub const NAME_TAG_HEIGHT: f32 = 2.6;
+const NAME_TAG_WIDTH: f32 = 200.0;
+
 pub struct RobotPlugin;
 
 #[derive(Component)]
@@ -35,24 +43,176 @@ struct Animations {
 #[derive(Component, Default, Debug)]
 pub struct Robot {
     target: Option<Vec3>,
+    /// Where to head once the target is reached, around the monuments in the way
+    waypoints: VecDeque<Vec3>,
+    /// Tiles per second
+    speed: f32,
     animation_timer: Option<Timer>,
     animation: Option<PlayerAnimation>,
 }
 
+/// Longest an emote can hold the robot, in case its clip never reports being finished
+const EMOTE_TIMEOUT: f32 = 5.0;
+
+impl Robot {
+    /// Play the emote once, unless the robot is on its way somewhere
+    pub fn emote(&mut self, kind: EmoteKind) {
+        if self.target.is_some() {
+            return;
+        }
+
+        self.animation = Some(kind.into());
+        self.animation_timer = None;
+    }
+
+    /// Run along the waypoints of a path, from the first one
+    fn walk(&mut self, path: Vec<Coordinate>, speed: f32) {
+        let mut waypoints: VecDeque<_> = path.iter().map(Coordinate::to_vec3).collect();
+
+        let Some(target) = waypoints.pop_front() else {
+            return;
+        };
+
+        self.target = Some(target);
+        self.waypoints = waypoints;
+        self.speed = speed;
+        self.animation = Some(PlayerAnimation::Running);
+        self.animation_timer = None;
+    }
+
+    fn stop(&mut self) {
+        self.target = None;
+        self.waypoints.clear();
+        self.animation = Some(PlayerAnimation::Idle);
+    }
+
+    /// Jump ahead along the path, to where the robot would be by now
+    fn advance(&mut self, transform: &mut Transform, mut distance: f32) {
+        while let Some(target) = self.target {
+            let left = transform.translation.distance(target);
+
+            if left > distance {
+                transform.translation += (target - transform.translation).normalize_or_zero() * distance;
+                return;
+            }
+
+            transform.translation = target;
+            distance -= left;
+            next_waypoint(self);
+        }
+    }
+}
+
+/// Enemies are played back this far behind the server, so there is always a move buffered
+const INTERPOLATION_DELAY: f64 = 0.1;
+
+/// Moves of an enemy robot waiting to be played back
+#[derive(Component, Default)]
+pub struct EnemyMotion {
+    moves: VecDeque<EnemyMove>,
+}
+
+struct EnemyMove {
+    /// Server time of the move, in seconds
+    at: f64,
+    coordinate: Coordinate,
+    speed: f32,
+}
+
+/// How far ahead the server clock is from this one, in seconds. Estimated from the timestamps of
+/// the moves received, the ones that took the least time to arrive being the most accurate
+#[derive(Resource, Default)]
+struct ServerClock {
+    offset: Option<f64>,
+}
+
+impl ServerClock {
+    /// How much lower estimates pull the offset back, in case the server clock went back in time
+    const DRIFT: f64 = 0.01;
+
+    fn observe(&mut self, timestamp: u64) {
+        let offset = timestamp as f64 / 1000.0 - now();
+
+        self.offset = Some(match self.offset {
+            Some(current) if offset < current => current + (offset - current) * Self::DRIFT,
+            _ => offset,
+        });
+    }
+
+    fn now(&self) -> f64 {
+        now() + self.offset.unwrap_or_default()
+    }
+}
+
+fn now() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+/// Tiles robots walk around, as far as the monuments loaded on this client go
+fn blocked_tiles(monuments: &Query<&Monument>) -> HashSet<Coordinate> {
+    monuments.iter().flat_map(|monument| monument.position.footprint()).collect()
+}
+
+/// Tile the robot is standing on, or about to
+fn standing_on(translation: Vec3) -> Coordinate {
+    translation.round().into()
+}
+
 #[derive(Component, Debug, Clone)]
 pub enum PlayerKind {
     MainPlayer(PlayerData),
     Enemy(PlayerData),
 }
 
+impl PlayerKind {
+    pub fn data(&self) -> &PlayerData {
+        match self {
+            PlayerKind::MainPlayer(data) | PlayerKind::Enemy(data) => data,
+        }
+    }
+
+    pub fn data_mut(&mut self) -> &mut PlayerData {
+        match self {
+            PlayerKind::MainPlayer(data) | PlayerKind::Enemy(data) => data,
+        }
+    }
+}
+
+/// Name of a robot
+#[derive(Component)]
+struct NameTag {
+    robot: Entity,
+}
+
+/// UI node kept right above a robot on screen, despawned along with the robot
+#[derive(Component)]
+pub struct FollowRobot {
+    pub robot: Entity,
+    /// How high above the robot's feet the bottom of the node sits
+    pub height: f32,
+    pub width: f32,
+}
+
 impl Plugin for RobotPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, listen_to_player_spawn_events_system);
         app.add_systems(Update, listen_to_player_balance_update);
         app.add_systems(Update, remove_disconnected_players_system);
         app.add_systems(Update, robots_movement_system);
+        app.insert_resource(ServerClock::default());
         app.add_systems(Update, listen_for_enemy_movement_system);
+        app.add_systems(Update, play_enemy_motion_system.after(listen_for_enemy_movement_system));
+        app.insert_resource(PendingMoves::default());
+        app.insert_resource(Reconciliation::default());
+        app.add_systems(Update, reconcile_main_player_system);
+        app.add_systems(Update, forget_robots_on_reconnect_system);
+        app.add_systems(Update, ease_reconciliation_system.after(reconcile_main_player_system));
+        app.add_systems(Update, listen_for_enemy_emote_system);
         app.add_systems(Update, move_robot_animation_system);
+        app.add_systems(Update, follow_robots_system);
+        app.add_systems(Update, listen_for_player_renamed_system);
+        app.insert_resource(Identity::default());
+        app.add_systems(Update, ask_player_name_system);
         app.add_systems(
             Update, calculate_player_movement_target_system.run_if(should_run).after(handle_build_monument_button_state_system),
         );
@@ -72,6 +232,29 @@ fn move_robot_animation_system(
             for child in children.iter_descendants(entity) {
                 if let Ok((mut player, mut transitions)) = query.get_mut(child) {
 
+                    if animation.is_emote() {
+                        let Some(timer) = robot.animation_timer.as_mut() else {
+                            // The timer only guards against clips that never finish
+                            transitions.play(&mut player, animation.to_index(), Duration::from_millis(250));
+                            robot.animation_timer = Some(Timer::from_seconds(EMOTE_TIMEOUT, TimerMode::Once));
+                            continue 'root;
+                        };
+
+                        timer.tick(time.delta());
+
+                        let finished = player
+                            .animation(animation.to_index())
+                            .map_or(true, |active| active.is_finished());
+
+                        if finished || timer.finished() {
+                            transitions.play(&mut player, PlayerAnimation::Idle.to_index(), Duration::from_millis(250)).repeat();
+                            robot.animation_timer = None;
+                            robot.animation = None;
+                        }
+
+                        continue 'root;
+                    }
+
                     // Hack to reset the pose of the model... no time to figure out what's wrong with it
                     if animation == PlayerAnimation::Idle && robot.animation_timer.is_none() {
                         transitions.play(
@@ -106,11 +289,19 @@ fn move_robot_animation_system(
 fn initialize_animations_observer(
     trigger: Trigger<SceneInstanceReady>,
     children: Query<&Children>,
-    animations: Query<&Animations, With<Robot>>,
+    animations: Query<(&Animations, &PlayerKind), With<Robot>>,
     mut commands: Commands,
     mut players: Query<&mut AnimationPlayer>,
+    mut parts: Query<(&mut MeshMaterial3d<StandardMaterial>, &GltfMaterialName)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    if let Ok(animations) = animations.get(trigger.entity()) {
+    if let Ok((animations, kind)) = animations.get(trigger.entity()) {
+        let cosmetics = kind.data().cosmetics;
+
+        tint_robot(trigger.entity(), cosmetics.color, &children, &mut parts, &mut materials);
+        equip_accessory(&mut commands, trigger.entity(), cosmetics.accessory, &mut meshes, &mut materials);
+
         for child in children.iter_descendants(trigger.entity()) {
             if let Ok(mut player) = players.get_mut(child) {
                 let mut transitions = AnimationTransitions::new();
@@ -185,20 +376,136 @@ fn spawn_player(
 
     let transform = Transform {
         scale: Vec3::splat(0.8),
-        translation: match &player_kind {
-            PlayerKind::MainPlayer(data) => data.position.to_vec3(),
-            PlayerKind::Enemy(data) => data.position.to_vec3(),
-        },
+        translation: player_kind.data().position.to_vec3(),
         ..default()
     };
 
-    commands
+    let name = player_kind.data().name.clone();
+
+    let robot = commands
         .spawn((animations, robot, transform, mesh, player_kind.clone()))
         .insert_if(Player::default(), || match player_kind {
             PlayerKind::MainPlayer(_) => true,
             PlayerKind::Enemy(_) => false
         })
-        .observe(initialize_animations_observer);
+        .insert_if(EnemyMotion::default(), || matches!(player_kind, PlayerKind::Enemy(_)))
+        .observe(initialize_animations_observer)
+        .id();
+
+    commands.spawn((
+        NameTag { robot },
+        FollowRobot { robot, height: NAME_TAG_HEIGHT, width: NAME_TAG_WIDTH },
+        Text::new(name),
+        TextFont::default().with_font_size(16.0),
+        TextColor(GRAY_800.into()),
+        TextLayout::new_with_justify(JustifyText::Center),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Px(NAME_TAG_WIDTH),
+            ..default()
+        },
+        Visibility::Hidden,
+    ));
+}
+
+fn follow_robots_system(
+    mut commands: Commands,
+    camera: Query<(&Camera, &GlobalTransform), With<Projection>>,
+    robots: Query<&GlobalTransform, With<Robot>>,
+    mut followers: Query<(Entity, &FollowRobot, &mut Node, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = camera.get_single() else {
+        return;
+    };
+
+    let Some(viewport) = camera.logical_viewport_size() else {
+        return;
+    };
+
+    for (entity, follow, mut node, mut visibility) in &mut followers {
+        let Ok(robot) = robots.get(follow.robot) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        match camera.world_to_viewport(camera_transform, robot.translation() + Vec3::Y * follow.height) {
+            Ok(position) => {
+                // Anchored by the bottom so taller nodes grow upwards
+                node.left = Val::Px(position.x - follow.width / 2.0);
+                node.bottom = Val::Px(viewport.y - position.y);
+                *visibility = Visibility::Inherited;
+            }
+            Err(_) => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+fn listen_for_player_renamed_system(
+    mut robots: Query<(Entity, &mut PlayerKind)>,
+    mut tags: Query<(&NameTag, &mut Text)>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        if let SystemMessages::PlayerRenamed { id, name } = &event.0 {
+            for (entity, mut kind) in &mut robots {
+                if kind.data().id != *id {
+                    continue;
+                }
+
+                kind.data_mut().name = name.clone();
+
+                for (tag, mut text) in &mut tags {
+                    if tag.robot == entity {
+                        text.0 = name.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Ask for a name once spawned, and again whenever the server refuses it. Spawned again after a
+/// reconnection, the name and looks the server knew the player by are asked for on their behalf
+fn ask_player_name_system(
+    mut identity: ResMut<Identity>,
+    mut server_events: EventReader<WebSocketMessageReceived>,
+    mut js_bridge_events: EventReader<JsBridgeMessageReceived>,
+    mut js_bridge: EventWriter<SendJsBridgeMessage>,
+    mut websocket: EventWriter<SendWebSocketMessage>,
+) {
+    for event in server_events.read() {
+        match &event.0 {
+            SystemMessages::MainPlayerSpawn { data } => {
+                identity.id = Some(data.id);
+
+                if let Some(name) = &identity.name {
+                    websocket.send(SendWebSocketMessage(SystemMessages::ChangeName { name: name.clone() }));
+                } else {
+                    js_bridge.send(SendJsBridgeMessage(JSBridgeMessages::CallAskName(None)));
+                }
+
+                if identity.cosmetics != data.cosmetics {
+                    websocket.send(SendWebSocketMessage(SystemMessages::ChangeCosmetics { cosmetics: identity.cosmetics }));
+                }
+            }
+            SystemMessages::NameRejected { reason } => {
+                js_bridge.send(SendJsBridgeMessage(JSBridgeMessages::CallAskName(Some(reason.clone()))));
+            }
+            SystemMessages::PlayerRenamed { id, name } if identity.id == Some(*id) => {
+                identity.name = Some(name.clone());
+            }
+            SystemMessages::PlayerCosmeticsChanged { id, cosmetics } if identity.id == Some(*id) => {
+                identity.cosmetics = *cosmetics;
+            }
+            _ => continue
+        }
+    }
+
+    for event in js_bridge_events.read() {
+        if let JSBridgeMessages::CallAskNameResponse(Some(name)) = &event.0 {
+            websocket.send(SendWebSocketMessage(SystemMessages::ChangeName { name: name.clone() }));
+        }
+    }
 }
 
 /// Only run when user click and there is a player spawn in the world
@@ -212,7 +519,7 @@ fn remove_disconnected_players_system(
     mut events: EventReader<WebSocketMessageReceived>,
 ) {
     for event in events.read() {
-        if let SystemMessages::EnemyDisconnected { id } = event.0 {
+        if let SystemMessages::EnemyDisconnected { id } | SystemMessages::EnemyOutOfView { id } = event.0 {
             for (entity, kind) in &mut robots {
                 if let PlayerKind::Enemy(data) = kind {
                     if data.id == id {
@@ -225,17 +532,203 @@ fn remove_disconnected_players_system(
 }
 
 fn listen_for_enemy_movement_system(
+    mut robots: Query<(&PlayerKind, &mut EnemyMotion)>,
+    mut clock: ResMut<ServerClock>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        if let SystemMessages::EnemyPosition { id, coordinate, timestamp, speed } = event.0 {
+            clock.observe(timestamp);
+
+            for (kind, mut motion) in &mut robots {
+                if kind.data().id == id {
+                    motion.moves.push_back(EnemyMove { at: timestamp as f64 / 1000.0, coordinate, speed });
+                }
+            }
+        }
+    }
+}
+
+/// Start the enemy's moves once they are [`INTERPOLATION_DELAY`] old, and catch up on the ones that
+/// arrived even later than that
+fn play_enemy_motion_system(
+    mut robots: Query<(&mut EnemyMotion, &mut Robot, &mut Transform)>,
+    monuments: Query<&Monument>,
+    clock: Res<ServerClock>,
+) {
+    let playback = clock.now() - INTERPOLATION_DELAY;
+    let mut blocked = None;
+
+    for (mut motion, mut robot, mut transform) in &mut robots {
+        while motion.moves.front().is_some_and(|next| next.at <= playback) {
+            let Some(next) = motion.moves.pop_front() else {
+                break;
+            };
+
+            let tiles = blocked.get_or_insert_with(|| blocked_tiles(&monuments));
+
+            // The server let the move through, this client is just missing some monuments
+            let path = find_path(standing_on(transform.translation), next.coordinate, |tile| tiles.contains(&tile))
+                .unwrap_or_else(|| vec![next.coordinate]);
+
+            robot.walk(path, next.speed);
+
+            let late = (playback - next.at) as f32;
+            robot.advance(&mut transform, late * next.speed);
+        }
+    }
+}
+
+/// What the server last accepted for the main player, outlives the connection
+#[derive(Resource, Default)]
+struct Identity {
+    /// Of the current session
+    id: Option<PlayerId>,
+    name: Option<String>,
+    cosmetics: Cosmetics,
+}
+
+/// Moves of the main player the server hasn't acknowledged yet, the robot already walks them
+#[derive(Resource, Default)]
+struct PendingMoves {
+    /// Of the last move sent
+    sequence: u32,
+    moves: VecDeque<(u32, Coordinate)>,
+}
+
+impl PendingMoves {
+    fn push(&mut self, coordinate: Coordinate) -> u32 {
+        self.sequence += 1;
+        self.moves.push_back((self.sequence, coordinate));
+        self.sequence
+    }
+
+    /// Forget the move and the ones before it, returns where it was headed
+    fn acknowledge(&mut self, sequence: u32) -> Option<Coordinate> {
+        let mut acknowledged = None;
+
+        while let Some((next, coordinate)) = self.moves.front().copied() {
+            if next > sequence {
+                break;
+            }
+
+            self.moves.pop_front();
+
+            if next == sequence {
+                acknowledged = Some(coordinate);
+            }
+        }
+
+        acknowledged
+    }
+}
+
+/// Rather than snapping, corrections of the main player are eased in at this rate, per second
+const RECONCILIATION_RATE: f32 = 10.0;
+
+/// Corrections bigger than this, in tiles, are snapped to
+const SNAP_DISTANCE: f32 = 8.0;
+
+/// Way left between where the main player is shown and where the server has it
+#[derive(Resource, Default)]
+struct Reconciliation {
+    error: Vec3,
+}
+
+/// Once every move is acknowledged, the robot should be on its way to, or standing on, the last
+/// position of the server. Otherwise it is eased back there
+fn reconcile_main_player_system(
+    mut pending: ResMut<PendingMoves>,
+    mut reconciliation: ResMut<Reconciliation>,
+    mut robots: Query<(&mut Robot, &mut Transform), With<Player>>,
+    mut events: EventReader<WebSocketMessageReceived>,
+) {
+    for event in events.read() {
+        let SystemMessages::MoveAcknowledged { sequence, coordinate } = event.0 else {
+            continue;
+        };
+
+        let predicted = pending.acknowledge(sequence);
+
+        // Moves sent since then will be acknowledged too
+        if pending.moves.is_empty() == false {
+            continue;
+        }
+
+        for (mut robot, mut transform) in &mut robots {
+            let error = (coordinate.to_vec3() - transform.translation).with_y(0.0);
+            let on_its_way = robot.target.is_some() && predicted == Some(coordinate);
+
+            if on_its_way || error.length() < 0.5 {
+                continue;
+            }
+
+            warn!("move {} corrected to {:?}", sequence, coordinate);
+            robot.stop();
+
+            if error.length() > SNAP_DISTANCE {
+                transform.translation += error;
+                reconciliation.error = Vec3::ZERO;
+            } else {
+                reconciliation.error = error;
+            }
+        }
+    }
+}
+
+/// The server spawns every robot again, main player included, under new ids
+fn forget_robots_on_reconnect_system(
+    mut commands: Commands,
+    robots: Query<Entity, With<PlayerKind>>,
+    mut pending: ResMut<PendingMoves>,
+    mut reconciliation: ResMut<Reconciliation>,
+    mut clock: ResMut<ServerClock>,
+    mut events: EventReader<Reconnected>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    for robot in &robots {
+        commands.entity(robot).despawn_recursive();
+    }
+
+    *pending = PendingMoves::default();
+    *reconciliation = Reconciliation::default();
+    *clock = ServerClock::default();
+}
+
+fn ease_reconciliation_system(
+    mut reconciliation: ResMut<Reconciliation>,
+    mut robots: Query<&mut Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    if reconciliation.error == Vec3::ZERO {
+        return;
+    }
+
+    let mut step = reconciliation.error * (1.0 - (-RECONCILIATION_RATE * time.delta_secs()).exp());
+
+    if (reconciliation.error - step).length() < 0.01 {
+        step = reconciliation.error;
+    }
+
+    for mut transform in &mut robots {
+        transform.translation += step;
+    }
+
+    reconciliation.error -= step;
+}
+
+fn listen_for_enemy_emote_system(
     mut robots: Query<(&PlayerKind, &mut Robot)>,
     mut events: EventReader<WebSocketMessageReceived>,
 ) {
     for event in events.read() {
-        if let SystemMessages::EnemyPosition { id, coordinate } = event.0 {
-            for (kind, mut robot) in &mut robots {
-                if let PlayerKind::Enemy(data) = kind {
-                    if data.id == id {
-                        robot.animation = Some(PlayerAnimation::Running);
-                        robot.target = Some(coordinate.to_vec3())
-                    }
+        if let SystemMessages::EnemyEmote { id, kind } = event.0 {
+            for (kind_of_player, mut robot) in &mut robots {
+                if kind_of_player.data().id == id {
+                    robot.emote(kind);
                 }
             }
         }
@@ -243,7 +736,8 @@ fn listen_for_enemy_movement_system(
 }
 
 fn robots_movement_system(
-    mut robots: Query<(&mut Robot, &mut Transform)>,
+    mut robots: Query<(&mut Robot, &mut Transform, Option<&Footprint>)>,
+    rapier_context: ReadRapierContext,
     time: Res<Time>,
     blocker: Res<UiInputBlocker>,
 ) {
@@ -251,15 +745,16 @@ fn robots_movement_system(
         return;
     }
 
-    for (mut robot, mut transform) in robots.iter_mut() {
+    let context = rapier_context.single();
+
+    for (mut robot, mut transform, footprint) in robots.iter_mut() {
         if let Some(target) = robot.target {
             let to_target = target - transform.translation;
             let distance = to_target.length();
 
             if distance < 0.01 {
                 transform.translation = target;
-                robot.target = None;
-                robot.animation = Some(PlayerAnimation::Idle);
+                next_waypoint(&mut robot);
                 continue;
             }
 
@@ -273,25 +768,40 @@ fn robots_movement_system(
                 transform.rotation = rotation;
             }
 
-            let max_step = 8.0 * time.delta_secs();
+            let max_step = robot.speed * time.delta_secs();
             let step = direction * distance.min(max_step);
+            let moved = resolve_movement(&context, transform.translation, step, footprint);
+
+            transform.translation += moved;
 
-            transform.translation += step;
+            // Wedged against a monument, other robots are walked through
+            if moved.length() < step.length() * 0.1 {
+                robot.stop();
+                continue;
+            }
 
-            if distance <= max_step {
+            if distance <= max_step && moved.distance(step) < 0.01 {
                 transform.translation = target;
-                robot.target = None;
-                robot.animation = Some(PlayerAnimation::Idle);
+                next_waypoint(&mut robot);
             }
         }
     }
 }
 
+fn next_waypoint(robot: &mut Robot) {
+    match robot.waypoints.pop_front() {
+        Some(waypoint) => robot.target = Some(waypoint),
+        None => robot.stop(),
+    }
+}
+
 fn calculate_player_movement_target_system(
     mut query: Query<(&mut Robot, &Transform), With<Player>>,
-    mut obstacles_query: Query<(&mut Sprite3d, &Transform), Without<Token>>,
+    monuments: Query<&Monument>,
     windows: Query<&Window>,
     camera: Query<(&Camera, &GlobalTransform), With<Projection>>,
+    mut pending: ResMut<PendingMoves>,
+    mut reconciliation: ResMut<Reconciliation>,
     mut event: EventWriter<SendWebSocketMessage>,
 ) {
     let (mut robot, player_transform) = query.single_mut();
@@ -311,73 +821,32 @@ fn calculate_player_movement_target_system(
                 intersection_point.x = (intersection_point.x / grid_size).round() * grid_size;
                 intersection_point.z = (intersection_point.z / grid_size).round() * grid_size;
 
-                let obstacles = obstacles_query
-                    .iter()
-                    .map(|(_, transform)| &transform.translation)
-                    .collect::<Vec<_>>();
-
-                let movement_target = find_closest_clear_path(
-                    &player_transform.translation,
-                    &intersection_point,
-                    &obstacles,
-                );
-
-                robot.animation = Some(PlayerAnimation::Running);
-                robot.target = Some(movement_target);
-
-                event.send(SendWebSocketMessage(SystemMessages::PlayerPosition { coordinate: movement_target.into() }));
-
-                info!("target -> {:?}", intersection_point);
-            }
-        }
-    }
-}
-
-fn find_closest_clear_path(start: &Vec3, end: &Vec3, elements: &Vec<&Vec3>) -> Vec3 {
-    let grid_size = 1.0;
-    let mut blocked = false;
-    let mut closest_safe_point = end.clone();
-
-    let band_half_length = 3.0 * grid_size;
-    let band_thickness = 1.0 * grid_size;
+                let goal: Coordinate = intersection_point.into();
+                let from = standing_on(player_transform.translation);
 
-    for obstacle in elements {
-        let delta = end - start;
-        let direction = delta.normalize_or_zero();
-        let steps = delta.length().ceil() as i32;
+                // The server turns these down anyway
+                if from.distance(&goal) > MAX_MOVE_DISTANCE {
+                    info!("too far to walk to {:?}", goal);
+                    return;
+                }
 
-        for index in 1..=steps {
-            let step_point = start + direction * (index as f32 * grid_size);
+                let blocked = blocked_tiles(&monuments);
 
-            // Offset from obstacle center
-            let dx = step_point.x - obstacle.x;
-            let dz = step_point.z - obstacle.z;
+                let Some(path) = find_path(from, goal, |tile| blocked.contains(&tile)) else {
+                    info!("no way to {:?}", goal);
+                    return;
+                };
 
-            // Project onto (1, -1) direction for isometric horizontal line
-            let iso_horizontal = (dx - dz) / 2.0;   // movement along the horizontal isometric line
-            let iso_vertical = (dx + dz) / 2.0;   // perpendicular offset (vertical band thickness)
+                // Predicted right away, the server acknowledges it later
+                robot.walk(path, ROBOT_SPEED);
+                reconciliation.error = Vec3::ZERO;
 
-            // Check if inside the isometric-aligned rectangle
-            if iso_horizontal.abs() <= band_half_length &&
-                iso_vertical.abs() <= band_thickness {
-                blocked = true;
+                let sequence = pending.push(goal);
+                event.send(SendWebSocketMessage(SystemMessages::PlayerPosition { coordinate: goal, sequence }));
 
-                closest_safe_point = start + direction * ((index - 1) as f32 * grid_size);
-                closest_safe_point.x = (closest_safe_point.x / grid_size).round() * grid_size;
-                closest_safe_point.z = (closest_safe_point.z / grid_size).round() * grid_size;
-                break;
+                info!("target -> {:?}", intersection_point);
             }
         }
-
-        if blocked {
-            break;
-        }
-    }
-
-    if blocked {
-        closest_safe_point
-    } else {
-        end.clone()
     }
 }
 
@@ -386,22 +855,34 @@ pub struct Player;
 
 #[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub enum PlayerAnimation {
+    Dance = 0,
     #[default]
     Idle = 2,
     Jumping = 3,
+    No = 4,
+    Punch = 5,
     Running = 6,
     Standing = 8,
+    ThumbsUp = 9,
     Walking = 10,
+    Wave = 12,
+    Yes = 13,
 }
 
 impl PlayerAnimation {
-    pub fn clips() -> [AssetPath<'static>; 5] {
+    pub fn clips() -> [AssetPath<'static>; 11] {
         [
             GltfAssetLabel::Animation(Self::Idle as usize).from_asset(ROBOT_GLB_PATH),
             GltfAssetLabel::Animation(Self::Jumping as usize).from_asset(ROBOT_GLB_PATH),
             GltfAssetLabel::Animation(Self::Running as usize).from_asset(ROBOT_GLB_PATH),
             GltfAssetLabel::Animation(Self::Walking as usize).from_asset(ROBOT_GLB_PATH),
             GltfAssetLabel::Animation(Self::Standing as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::Wave as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::ThumbsUp as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::Dance as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::Yes as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::No as usize).from_asset(ROBOT_GLB_PATH),
+            GltfAssetLabel::Animation(Self::Punch as usize).from_asset(ROBOT_GLB_PATH),
         ]
     }
 
@@ -412,7 +893,38 @@ impl PlayerAnimation {
             PlayerAnimation::Running => 3.into(),
             PlayerAnimation::Walking => 4.into(),
             PlayerAnimation::Standing => 5.into(),
+            PlayerAnimation::Wave => 6.into(),
+            PlayerAnimation::ThumbsUp => 7.into(),
+            PlayerAnimation::Dance => 8.into(),
+            PlayerAnimation::Yes => 9.into(),
+            PlayerAnimation::No => 10.into(),
+            PlayerAnimation::Punch => 11.into(),
         }
     }
+
+    /// Emotes are played once, then the robot goes back to idling
+    pub fn is_emote(&self) -> bool {
+        matches!(
+            self,
+            PlayerAnimation::Wave
+                | PlayerAnimation::ThumbsUp
+                | PlayerAnimation::Dance
+                | PlayerAnimation::Yes
+                | PlayerAnimation::No
+                | PlayerAnimation::Punch
+        )
+    }
 }
 
+impl From<EmoteKind> for PlayerAnimation {
+    fn from(kind: EmoteKind) -> Self {
+        match kind {
+            EmoteKind::Wave => PlayerAnimation::Wave,
+            EmoteKind::ThumbsUp => PlayerAnimation::ThumbsUp,
+            EmoteKind::Dance => PlayerAnimation::Dance,
+            EmoteKind::Yes => PlayerAnimation::Yes,
+            EmoteKind::No => PlayerAnimation::No,
+            EmoteKind::Punch => PlayerAnimation::Punch,
+        }
+    }
+}
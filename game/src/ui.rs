@@ -1,8 +1,10 @@
+use crate::builder::Placement;
 use crate::js_bridge_plugin::{JSBridgeMessages, SendJsBridgeMessage};
+use crate::network::{ConnectionState, SendWebSocketMessage, WebSocketMessageReceived};
 use crate::robot::{Player, PlayerKind, Robot};
 use bevy::color::palettes::tailwind::*;
 use bevy::prelude::*;
-use shared::PlayerData;
+use shared::{EmoteKind, PlayerData, SystemMessages, MONUMENT_PRICE};
 
 #[derive(Resource, Default)]
 pub struct UiInputBlocker(pub bool);
@@ -12,6 +14,29 @@ pub struct UIPlugin;
 #[derive(Component)]
 struct CoordinateText;
 
+#[derive(Component)]
+struct WaitingRoomText;
+
+/// Shown across the top of the screen while the server can't be reached
+#[derive(Component)]
+struct ConnectionBanner;
+
+#[derive(Component)]
+pub struct BuildMonumentButton;
+
+#[derive(Component)]
+struct ShopButton;
+
+/// Opened with the E key, lists every emote around the center of the screen
+#[derive(Component)]
+struct EmoteWheel;
+
+#[derive(Component)]
+struct EmoteButton(EmoteKind);
+
+const EMOTE_WHEEL_RADIUS: f32 = 120.0;
+const EMOTE_BUTTON_SIZE: Vec2 = Vec2::new(100.0, 40.0);
+
 const NORMAL_BUTTON: Srgba = GREEN_600;
 const HOVERED_BUTTON: Srgba = GREEN_700;
 const PRESSED_BUTTON: Srgba = GREEN_500;
@@ -23,9 +48,18 @@ impl Plugin for UIPlugin {
 
         app.add_systems(Startup, add_coordinate_to_screen_system);
         app.add_systems(Startup, add_build_monument_button_system);
+        app.add_systems(Startup, add_shop_button_system);
+        app.add_systems(Startup, add_emote_wheel_system);
+        app.add_systems(Update, toggle_emote_wheel_system);
+        app.add_systems(Startup, add_waiting_room_text_system);
+        app.add_systems(Update, update_waiting_room_system);
+        app.add_systems(Startup, add_connection_banner_system);
+        app.add_systems(Update, update_connection_banner_system);
         app.add_systems(Update, update_coordinate_system);
         app.add_systems(Update, update_balance_system);
         app.add_systems(Update, handle_build_monument_button_state_system);
+        app.add_systems(Update, handle_shop_button_state_system.after(reset_ui_blocker).before(handle_build_monument_button_state_system));
+        app.add_systems(Update, handle_emote_button_state_system.after(reset_ui_blocker).before(handle_build_monument_button_state_system));
         app.add_systems(Update, reset_ui_blocker.before(handle_build_monument_button_state_system));
     }
 }
@@ -61,8 +95,105 @@ fn add_coordinate_to_screen_system(mut commands: Commands) {
     ));
 }
 
+fn add_waiting_room_text_system(mut commands: Commands) {
+    commands.spawn((
+        WaitingRoomText,
+        Text::new(""),
+        TextFont::default().with_font_size(28.0),
+        TextColor(GRAY_800.into()),
+        TextLayout::new_with_justify(JustifyText::Center),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(40.0),
+            width: Val::Percent(100.0),
+            ..default()
+        },
+        Visibility::Hidden,
+    ));
+}
+
+fn add_connection_banner_system(mut commands: Commands) {
+    commands
+        .spawn((
+            ConnectionBanner,
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.0),
+                width: Val::Percent(100.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(AMBER_400.into()),
+            Visibility::Hidden,
+        ))
+        .with_child((
+            Text::new(""),
+            TextFont::default().with_font_size(16.0),
+            TextColor(GRAY_800.into()),
+        ));
+}
+
+fn update_connection_banner_system(
+    state: Res<ConnectionState>,
+    mut banner: Query<(&mut Visibility, &mut BackgroundColor, &Children), With<ConnectionBanner>>,
+    mut texts: Query<&mut Text>,
+) {
+    if state.is_changed() == false {
+        return;
+    }
+
+    let Ok((mut visibility, mut background, children)) = banner.get_single_mut() else {
+        return;
+    };
+
+    let (message, color) = match *state {
+        ConnectionState::Connected => {
+            *visibility = Visibility::Hidden;
+            return;
+        }
+        ConnectionState::Connecting => ("Connecting to the server...".to_string(), AMBER_400),
+        ConnectionState::Reconnecting { attempt } => {
+            (format!("Connection lost, reconnecting (attempt {})...", attempt), AMBER_400)
+        }
+        ConnectionState::Failed => ("Disconnected from the server, reload the page to try again".to_string(), RED_400),
+    };
+
+    for child in children.iter() {
+        if let Ok(mut text) = texts.get_mut(*child) {
+            text.0 = message.clone();
+        }
+    }
+
+    background.0 = color.into();
+    *visibility = Visibility::Visible;
+}
+
+/// The server is full, show our place in line until the main player gets spawned
+fn update_waiting_room_system(
+    mut events: EventReader<WebSocketMessageReceived>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<WaitingRoomText>>,
+) {
+    for event in events.read() {
+        let Ok((mut text, mut visibility)) = text_query.get_single_mut() else {
+            return;
+        };
+
+        match &event.0 {
+            SystemMessages::WaitingRoom { position } => {
+                text.0 = format!("The world is full\nYou are #{} in line", position);
+                *visibility = Visibility::Visible;
+            }
+            SystemMessages::MainPlayerSpawn { .. } => {
+                *visibility = Visibility::Hidden;
+            }
+            _ => continue
+        }
+    }
+}
+
 fn update_balance_system(
-    mut button_query: Query<(&mut BackgroundColor, &Children), With<Button>>,
+    mut button_query: Query<(&mut BackgroundColor, &Children), With<BuildMonumentButton>>,
     mut text_query: Query<&mut Text>,
     mut player_query: Query<&PlayerKind, (With<Player>, Changed<PlayerKind>)>,
 ) {
@@ -72,10 +203,10 @@ fn update_balance_system(
         let (mut background, children) = button_query.single_mut();
 
         if let Ok(mut text) = text_query.get_mut(children[1]) {
-            **text = format!("Tokens ({}/5)", balance);
+            **text = format!("Tokens ({}/{})", balance, MONUMENT_PRICE);
         }
 
-        if balance < 5 {
+        if balance < MONUMENT_PRICE {
             background.0 = DISABLED_BUTTON.into();
         } else {
             background.0 = NORMAL_BUTTON.into();
@@ -91,9 +222,9 @@ fn get_balance(metadata: &PlayerKind) -> u32 {
 }
 
 pub fn handle_build_monument_button_state_system(
-    mut interaction_query: Query<(&Interaction, &mut BackgroundColor, &Children), (Changed<Interaction>, With<Button>)>,
-    mut event: EventWriter<SendJsBridgeMessage>,
+    mut interaction_query: Query<(&Interaction, &mut BackgroundColor, &Children), (Changed<Interaction>, With<BuildMonumentButton>)>,
     mut blocker: ResMut<UiInputBlocker>,
+    mut placement: ResMut<Placement>,
     mut player_query: Query<&PlayerKind, With<Player>>,
 ) {
     let balance = {
@@ -105,14 +236,14 @@ pub fn handle_build_monument_button_state_system(
     };
 
     for (interaction, mut background_color, children) in &mut interaction_query {
-        if balance < 5 {
+        if balance < MONUMENT_PRICE {
             continue;
         }
 
         match *interaction {
             Interaction::Pressed => {
                 blocker.0 = true;
-                event.send(SendJsBridgeMessage(JSBridgeMessages::CallOpenModal));
+                *placement = Placement::Picking;
                 background_color.0 = PRESSED_BUTTON.into();
             }
             Interaction::Hovered => {
@@ -141,6 +272,7 @@ fn add_build_monument_button_system(mut commands: Commands) {
     button.with_children(|parent| {
         let mut parent = parent.spawn((
             Button,
+            BuildMonumentButton,
             Node {
                 width: Val::Px(150.0),
                 height: Val::Px(65.0),
@@ -169,6 +301,167 @@ fn add_build_monument_button_system(mut commands: Commands) {
     });
 }
 
-fn reset_ui_blocker(mut blocker: ResMut<UiInputBlocker>) {
+fn handle_shop_button_state_system(
+    mut interaction_query: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<ShopButton>)>,
+    mut event: EventWriter<SendJsBridgeMessage>,
+    mut blocker: ResMut<UiInputBlocker>,
+    player_query: Query<&PlayerKind, With<Player>>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+
+    for (interaction, mut background_color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                let data = player.data();
+
+                blocker.0 = true;
+                event.send(SendJsBridgeMessage(JSBridgeMessages::CallOpenShop(data.balance, data.cosmetics)));
+                background_color.0 = PRESSED_BUTTON.into();
+            }
+            Interaction::Hovered => {
+                background_color.0 = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                background_color.0 = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+fn add_shop_button_system(mut commands: Commands) {
+    commands
+        .spawn((
+            Button,
+            ShopButton,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Px(150.0),
+                height: Val::Px(40.0),
+                right: Val::Px(10.0),
+                bottom: Val::Px(85.0),
+                border: UiRect::all(Val::Px(5.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BorderRadius::all(Val::Px(16.0)),
+            BackgroundColor(NORMAL_BUTTON.into()),
+        ))
+        .with_child((
+            Text::new("Shop"),
+            TextFont::default().with_font_size(18.0),
+            TextColor(Color::srgb(0.9, 0.9, 0.9))
+        ));
+}
+
+fn add_emote_wheel_system(mut commands: Commands) {
+    let mut root = commands.spawn((
+        EmoteWheel,
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        Visibility::Hidden,
+    ));
+
+    root.with_children(|parent| {
+        let mut wheel = parent.spawn(Node {
+            width: Val::Px(EMOTE_WHEEL_RADIUS * 2.0),
+            height: Val::Px(EMOTE_WHEEL_RADIUS * 2.0),
+            ..default()
+        });
+
+        wheel.with_children(|parent| {
+            for (index, kind) in EmoteKind::ALL.iter().enumerate() {
+                let angle = std::f32::consts::TAU * index as f32 / EmoteKind::ALL.len() as f32;
+                let center = Vec2::splat(EMOTE_WHEEL_RADIUS) + Vec2::from_angle(angle).rotate(Vec2::NEG_Y) * EMOTE_WHEEL_RADIUS;
+
+                parent
+                    .spawn((
+                        Button,
+                        EmoteButton(*kind),
+                        Node {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(center.x - EMOTE_BUTTON_SIZE.x / 2.0),
+                            top: Val::Px(center.y - EMOTE_BUTTON_SIZE.y / 2.0),
+                            width: Val::Px(EMOTE_BUTTON_SIZE.x),
+                            height: Val::Px(EMOTE_BUTTON_SIZE.y),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        BorderRadius::all(Val::Px(20.0)),
+                        BackgroundColor(NORMAL_BUTTON.into()),
+                    ))
+                    .with_child((
+                        Text::new(format!("{:?}", kind)),
+                        TextFont::default().with_font_size(16.0),
+                        TextColor(Color::srgb(0.9, 0.9, 0.9))
+                    ));
+            }
+        });
+    });
+}
+
+fn toggle_emote_wheel_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut wheel: Query<&mut Visibility, With<EmoteWheel>>,
+    player_query: Query<(), With<Player>>,
+) {
+    let Ok(mut visibility) = wheel.get_single_mut() else {
+        return;
+    };
+
+    if player_query.is_empty() {
+        *visibility = Visibility::Hidden;
+    } else if keyboard.just_pressed(KeyCode::KeyE) {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+fn handle_emote_button_state_system(
+    mut interaction_query: Query<(&Interaction, &EmoteButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut wheel: Query<&mut Visibility, With<EmoteWheel>>,
+    mut player_query: Query<&mut Robot, With<Player>>,
+    mut websocket: EventWriter<SendWebSocketMessage>,
+    mut blocker: ResMut<UiInputBlocker>,
+) {
+    for (interaction, button, mut background_color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                blocker.0 = true;
+                background_color.0 = PRESSED_BUTTON.into();
+
+                if let Ok(mut robot) = player_query.get_single_mut() {
+                    robot.emote(button.0);
+                    websocket.send(SendWebSocketMessage(SystemMessages::Emote { kind: button.0 }));
+                }
+
+                if let Ok(mut visibility) = wheel.get_single_mut() {
+                    *visibility = Visibility::Hidden;
+                }
+            }
+            Interaction::Hovered => {
+                background_color.0 = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                background_color.0 = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+pub fn reset_ui_blocker(mut blocker: ResMut<UiInputBlocker>) {
     blocker.0 = false;
 }
\ No newline at end of file
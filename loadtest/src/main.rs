@@ -0,0 +1,300 @@
+//! Simulates players wandering around, picking up tokens and building monuments.
+//!
+//! Run the server with the stub image generator and a raised per address limit, e.g.
+//! `IMAGE_GENERATOR=stub MAX_CONNECTIONS_PER_IP=10000 ADMIN_TOKEN=secret cargo run -p server --release`
+//! then `PLAYERS=1000 DURATION=120 ADMIN_TOKEN=secret cargo run -p loadtest --release`.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use client::Client;
+use shared::{Coordinate, SystemMessages, MONUMENT_PRICE};
+
+/// How often every bot pings the server to measure the round trip through the game loop
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far from its current position a bot walks at once
+const WANDER_DISTANCE: i32 = 6;
+
+/// How far from its current position a bot places its monuments
+const BUILD_DISTANCE: i32 = 10;
+
+#[derive(Default)]
+struct Stats {
+    connected: AtomicU64,
+    failed_to_connect: AtomicU64,
+    dropped: AtomicU64,
+    evicted: AtomicU64,
+    rate_limited: AtomicU64,
+    sent: AtomicU64,
+    received: AtomicU64,
+    builds: AtomicU64,
+    latencies: Mutex<Vec<Duration>>,
+}
+
+struct Settings {
+    url: String,
+    metrics_url: String,
+    /// The server only serves metrics to operators
+    admin_token: String,
+    players: u64,
+    ramp_up: Duration,
+    duration: Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let settings = Settings {
+        url: env_or("SERVER_URL", "ws://127.0.0.1:3000/ws".to_string()),
+        metrics_url: env_or("METRICS_URL", "http://127.0.0.1:3000/metrics".to_string()),
+        admin_token: env_or("ADMIN_TOKEN", String::new()),
+        players: env_or("PLAYERS", 100),
+        ramp_up: Duration::from_secs(env_or("RAMP_UP", 10)),
+        duration: Duration::from_secs(env_or("DURATION", 60)),
+    };
+
+    println!(
+        "connecting {} players to {} over {:?}, then running for {:?}",
+        settings.players, settings.url, settings.ramp_up, settings.duration
+    );
+
+    let memory_before = resident_memory(&settings).await;
+    let stats = Arc::new(Stats::default());
+    let started = Instant::now();
+    let deadline = started + settings.ramp_up + settings.duration;
+
+    let mut bots = JoinSet::new();
+
+    for index in 0..settings.players {
+        let delay = settings.ramp_up.mul_f64(index as f64 / settings.players as f64);
+        bots.spawn(bot(settings.url.clone(), stats.clone(), started + delay, deadline));
+    }
+
+    let mut progress = tokio::time::interval(Duration::from_secs(5));
+    progress.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    progress.tick().await;
+
+    let mut measured_from = None;
+
+    loop {
+        tokio::select! {
+            _ = progress.tick() => {
+                println!(
+                    "{:>4}s  connected {}  dropped {}  sent {}  received {}",
+                    started.elapsed().as_secs(),
+                    stats.connected.load(Ordering::Relaxed),
+                    stats.dropped.load(Ordering::Relaxed),
+                    stats.sent.load(Ordering::Relaxed),
+                    stats.received.load(Ordering::Relaxed),
+                );
+
+                // Throughput only counts once every player had the chance to connect
+                if measured_from.is_none() && started.elapsed() >= settings.ramp_up {
+                    measured_from = Some(snapshot(&stats));
+                }
+            }
+            finished = bots.join_next() => if finished.is_none() {
+                break;
+            },
+        }
+    }
+
+    let memory_after = resident_memory(&settings).await;
+    let (measured_at, sent_before, received_before) = measured_from.unwrap_or((started, 0, 0));
+    let (ended, sent, received) = snapshot(&stats);
+    let elapsed = (ended - measured_at).as_secs_f64().max(f64::EPSILON);
+
+    println!();
+    println!(
+        "players       {} connected, {} failed to connect, {} dropped ({} evicted)",
+        stats.connected.load(Ordering::Relaxed),
+        stats.failed_to_connect.load(Ordering::Relaxed),
+        stats.dropped.load(Ordering::Relaxed),
+        stats.evicted.load(Ordering::Relaxed),
+    );
+    println!(
+        "throughput    sent {:.0} msg/s, received {:.0} msg/s",
+        (sent - sent_before) as f64 / elapsed,
+        (received - received_before) as f64 / elapsed,
+    );
+
+    let mut latencies = std::mem::take(&mut *stats.latencies.lock().unwrap());
+    latencies.sort();
+
+    if latencies.is_empty() {
+        println!("latency       no pong received");
+    } else {
+        println!(
+            "latency       p50 {}  p95 {}  p99 {}  max {}  ({} pings)",
+            milliseconds(percentile(&latencies, 0.50)),
+            milliseconds(percentile(&latencies, 0.95)),
+            milliseconds(percentile(&latencies, 0.99)),
+            milliseconds(latencies[latencies.len() - 1]),
+            latencies.len(),
+        );
+    }
+
+    println!(
+        "activity      {} monuments requested, {} rate limit notices",
+        stats.builds.load(Ordering::Relaxed),
+        stats.rate_limited.load(Ordering::Relaxed),
+    );
+
+    match (memory_before, memory_after) {
+        (Some(before), Some(after)) => println!("server memory {} -> {}", mebibytes(before), mebibytes(after)),
+        _ => println!("server memory unavailable, is {} reachable with ADMIN_TOKEN?", settings.metrics_url),
+    }
+
+    Ok(())
+}
+
+/// A single player, wanders around until the deadline or until the server lets go of it
+async fn bot(url: String, stats: Arc<Stats>, start_at: Instant, deadline: Instant) {
+    tokio::time::sleep_until(start_at).await;
+
+    let client = match Client::connect(&url).await {
+        Ok(client) => client,
+        Err(_) => {
+            stats.failed_to_connect.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    stats.connected.fetch_add(1, Ordering::Relaxed);
+
+    let mut position = client.player().position;
+    let mut sequence = 0;
+    let mut balance = client.player().balance;
+    let (mut actions, mut events) = client.split();
+
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut wander = tokio::time::interval(Duration::from_millis(fastrand::u64(500..1500)));
+    let mut pings = VecDeque::new();
+
+    loop {
+        let message = tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {
+                let _ = actions.close().await;
+                return;
+            }
+            _ = ping.tick() => {
+                pings.push_back(Instant::now());
+                Some(SystemMessages::Ping)
+            }
+            _ = wander.tick() => {
+                if balance >= MONUMENT_PRICE && fastrand::u8(..10) == 0 {
+                    balance -= MONUMENT_PRICE;
+                    stats.builds.fetch_add(1, Ordering::Relaxed);
+                    Some(SystemMessages::BuildMonumentRequest {
+                        prompt: "a robot made of robots".into(),
+                        coordinate: Coordinate {
+                            x: position.x + fastrand::i32(-BUILD_DISTANCE..=BUILD_DISTANCE),
+                            y: position.y + fastrand::i32(-BUILD_DISTANCE..=BUILD_DISTANCE),
+                        },
+                    })
+                } else if fastrand::bool() {
+                    Some(SystemMessages::MainPlayerPickedUpToken)
+                } else {
+                    position = Coordinate {
+                        x: position.x + fastrand::i32(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                        y: position.y + fastrand::i32(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                    };
+
+                    sequence += 1;
+                    Some(SystemMessages::PlayerPosition { coordinate: position, sequence })
+                }
+            }
+            event = events.next() => {
+                match event {
+                    Some(Ok(message)) => {
+                        stats.received.fetch_add(1, Ordering::Relaxed);
+
+                        match message {
+                            SystemMessages::Pong => {
+                                if let Some(sent) = pings.pop_front() {
+                                    stats.latencies.lock().unwrap().push(sent.elapsed());
+                                }
+                            }
+                            SystemMessages::MainPlayerCurrentBalance { balance: current } => balance = current,
+                            // Wandered into a monument
+                            SystemMessages::MoveAcknowledged { sequence: acknowledged, coordinate } if acknowledged == sequence => {
+                                position = coordinate;
+                            }
+                            SystemMessages::RateLimited { .. } => {
+                                stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+                            }
+                            SystemMessages::Evicted { .. } => {
+                                stats.evicted.fetch_add(1, Ordering::Relaxed);
+                            }
+                            _ => {}
+                        }
+
+                        None
+                    }
+                    Some(Err(_)) | None => {
+                        stats.dropped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+        };
+
+        if let Some(message) = message {
+            if actions.send(message).await.is_err() {
+                stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            stats.sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn snapshot(stats: &Stats) -> (Instant, u64, u64) {
+    (Instant::now(), stats.sent.load(Ordering::Relaxed), stats.received.load(Ordering::Relaxed))
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    sorted[index]
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn mebibytes(bytes: f64) -> String {
+    format!("{:.1} MiB", bytes / 1024.0 / 1024.0)
+}
+
+/// Scraped from the server's `/metrics` route
+async fn resident_memory(settings: &Settings) -> Option<f64> {
+    let metrics = reqwest::Client::new()
+        .get(&settings.metrics_url)
+        .bearer_auth(&settings.admin_token)
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix("process_resident_memory_bytes "))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
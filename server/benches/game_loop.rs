@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use server::admission::Capacity;
+use server::generator::StubGenerator;
+use server::game_loop::GameLoop;
+use server::outbox;
+use server::world::World;
+use shared::{Coordinate, PlayerId, SystemMessages, CHUNK_SIZE};
+
+/// A game loop with every player connected through an in-memory outbox instead of a socket
+struct Simulation {
+    game: GameLoop,
+    players: Vec<PlayerId>,
+    /// Enemy positions received across every simulated client
+    delivered: Arc<AtomicUsize>,
+}
+
+impl Simulation {
+    /// Connect every player and wait until each of them has been told about everyone else
+    async fn connect(players: usize) -> Self {
+        let game = GameLoop::spawn(World::default(), Capacity { max_players: players, max_waiting: 0 }, StubGenerator::default());
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let spawned = Arc::new(AtomicUsize::new(0));
+
+        let ids = (0..players)
+            .map(|_| {
+                let id = PlayerId::random();
+                let (sender, mut receiver) = outbox::channel(players * 2);
+                let delivered = delivered.clone();
+                let spawned = spawned.clone();
+
+                tokio::spawn(async move {
+                    while let Some(message) = receiver.recv().await {
+                        match message {
+                            SystemMessages::EnemyPosition { .. } => delivered.fetch_add(1, Ordering::Relaxed),
+                            SystemMessages::EnemyPlayerSpawn { .. } => spawned.fetch_add(1, Ordering::Relaxed),
+                            _ => 0,
+                        };
+                    }
+                });
+
+                game.connect(id, sender);
+                id
+            })
+            .collect();
+
+        while spawned.load(Ordering::Relaxed) < players * (players - 1) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        Self { game, players: ids, delivered }
+    }
+
+    /// Every player moves once, resolves when everyone else has been told about it
+    async fn round(&self) {
+        let fan_out = self.players.len() * (self.players.len() - 1);
+        let expected = self.delivered.load(Ordering::Relaxed) + fan_out;
+        // Everyone stays within the spawn chunk so that they all keep seeing each other
+        let coordinate = Coordinate { x: fastrand::i32(0..CHUNK_SIZE), y: fastrand::i32(0..CHUNK_SIZE) };
+
+        for id in &self.players {
+            self.game.message(*id, SystemMessages::PlayerPosition { coordinate, sequence: 0 });
+        }
+
+        while self.delivered.load(Ordering::Relaxed) < expected {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+}
+
+fn movement_fan_out(criterion: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = criterion.benchmark_group("movement_fan_out");
+
+    group.sample_size(10);
+
+    for players in [500, 1000, 2500] {
+        let simulation = runtime.block_on(Simulation::connect(players));
+
+        group.throughput(Throughput::Elements((players * (players - 1)) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(players), &simulation, |bencher, simulation| {
+            bencher.to_async(&runtime).iter(|| simulation.round());
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, movement_fan_out);
+criterion_main!(benches);
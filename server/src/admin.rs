@@ -0,0 +1,94 @@
+use axum::extract::{Path, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use shared::{PlayerData, PlayerId};
+
+use crate::game_loop::{GameLoop, PendingGeneration};
+
+/// Routes to operate a live world, every request must carry `Authorization: Bearer <token>`
+pub fn router<S>(game: GameLoop, token: String) -> Router<S> {
+    Router::new()
+        .route("/players", get(list_players))
+        .route("/players/{id}/kick", post(kick_player))
+        .route("/players/{id}/balance", post(adjust_balance))
+        .route("/monuments/{id}", delete(remove_monument))
+        .route("/monuments/{id}/hide", post(hide_monument))
+        .route("/monuments/{id}/show", post(show_monument))
+        .route("/generations", get(list_generations))
+        .layer(from_fn_with_state(token, authorize))
+        .with_state(game)
+}
+
+/// Shared with every other operator-only route
+pub(crate) async fn authorize(State(token): State<String>, request: Request, next: Next) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => next.run(request).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Compare without short-circuiting so the token can't be guessed byte by byte through timing
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn found(found: bool) -> StatusCode {
+    if found { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }
+}
+
+async fn list_players(State(game): State<GameLoop>) -> Json<Vec<PlayerData>> {
+    Json(game.players().await)
+}
+
+async fn kick_player(State(game): State<GameLoop>, Path(id): Path<u32>) -> StatusCode {
+    found(game.kick(PlayerId::from(id)).await)
+}
+
+#[derive(Deserialize)]
+struct BalanceAdjustment {
+    /// Tokens to add, negative to take tokens away
+    amount: i64,
+}
+
+#[derive(Serialize)]
+struct Balance {
+    balance: u32,
+}
+
+async fn adjust_balance(
+    State(game): State<GameLoop>,
+    Path(id): Path<u32>,
+    Json(adjustment): Json<BalanceAdjustment>,
+) -> Result<Json<Balance>, StatusCode> {
+    game.adjust_balance(PlayerId::from(id), adjustment.amount)
+        .await
+        .map(|balance| Json(Balance { balance }))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn remove_monument(State(game): State<GameLoop>, Path(id): Path<u32>) -> StatusCode {
+    found(game.remove_monument(id).await)
+}
+
+async fn hide_monument(State(game): State<GameLoop>, Path(id): Path<u32>) -> StatusCode {
+    found(game.set_monument_hidden(id, true).await)
+}
+
+async fn show_monument(State(game): State<GameLoop>, Path(id): Path<u32>) -> StatusCode {
+    found(game.set_monument_hidden(id, false).await)
+}
+
+async fn list_generations(State(game): State<GameLoop>) -> Json<Vec<PendingGeneration>> {
+    Json(game.generations().await)
+}
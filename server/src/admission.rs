@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use shared::{PlayerId, SystemMessages};
+
+use crate::outbox::Sender;
+
+/// How many players the world holds at once, and how many more may queue for a slot
+#[derive(Debug, Clone, Copy)]
+pub struct Capacity {
+    pub max_players: usize,
+    pub max_waiting: usize,
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Self { max_players: 500, max_waiting: 1000 }
+    }
+}
+
+/// Counts open connections per address, checked before the websocket handshake even starts
+#[derive(Clone)]
+pub struct ConnectionsPerIp {
+    limit: usize,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionsPerIp {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, open: Arc::default() }
+    }
+
+    /// Reserve a connection for this address, the reservation is released once the permit is dropped
+    pub fn acquire(&self, address: IpAddr) -> Option<Permit> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(address).or_default();
+
+        if *count >= self.limit {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(Permit { address, open: self.open.clone() })
+    }
+}
+
+pub struct Permit {
+    address: IpAddr,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap();
+
+        if let Some(count) = open.get_mut(&self.address) {
+            *count -= 1;
+
+            if *count == 0 {
+                open.remove(&self.address);
+            }
+        }
+    }
+}
+
+/// Players waiting for a free slot, in order of arrival
+#[derive(Default)]
+pub struct WaitingRoom {
+    queue: VecDeque<(PlayerId, Sender)>,
+}
+
+impl WaitingRoom {
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queue the player at the back and tell them their position
+    pub fn join(&mut self, id: PlayerId, sender: Sender) {
+        let _ = sender.send(SystemMessages::WaitingRoom { position: self.queue.len() as u32 + 1 });
+        self.queue.push_back((id, sender));
+    }
+
+    /// The player gave up waiting, everyone behind moves up
+    pub fn leave(&mut self, id: PlayerId) -> bool {
+        let Some(index) = self.queue.iter().position(|(waiting, _)| *waiting == id) else {
+            return false;
+        };
+
+        self.queue.remove(index);
+        self.notify_positions(index);
+
+        true
+    }
+
+    /// Take the player at the front of the line
+    pub fn admit(&mut self) -> Option<(PlayerId, Sender)> {
+        let next = self.queue.pop_front()?;
+        self.notify_positions(0);
+
+        Some(next)
+    }
+
+    /// Deliver a final message to everyone still waiting and empty the room
+    pub fn shutdown(&mut self, message: SystemMessages) -> usize {
+        let waiting = self.queue.len();
+
+        for (_, sender) in self.queue.drain(..) {
+            sender.close_with(message.clone());
+        }
+
+        waiting
+    }
+
+    fn notify_positions(&self, from: usize) {
+        for (index, (_, sender)) in self.queue.iter().enumerate().skip(from) {
+            let _ = sender.send(SystemMessages::WaitingRoom { position: index as u32 + 1 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::outbox;
+
+    use super::*;
+
+    #[test]
+    fn limits_connections_per_address_until_released() {
+        let connections = ConnectionsPerIp::new(2);
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let first = connections.acquire(address);
+        let second = connections.acquire(address);
+
+        assert!(first.is_some() && second.is_some());
+        assert!(connections.acquire(address).is_none());
+        assert!(connections.acquire(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).is_some());
+
+        drop(first);
+
+        assert!(connections.acquire(address).is_some());
+    }
+
+    #[tokio::test]
+    async fn moves_everyone_up_when_a_player_leaves() {
+        let mut room = WaitingRoom::default();
+        let (first, _first) = outbox::channel(8);
+        let (second, mut second_receiver) = outbox::channel(8);
+
+        room.join(PlayerId::from(1), first);
+        room.join(PlayerId::from(2), second);
+
+        assert!(matches!(second_receiver.recv().await, Some(SystemMessages::WaitingRoom { position: 2 })));
+
+        assert!(room.leave(PlayerId::from(1)));
+        assert!(matches!(second_receiver.recv().await, Some(SystemMessages::WaitingRoom { position: 1 })));
+
+        let (admitted, _) = room.admit().unwrap();
+
+        assert_eq!(admitted, PlayerId::from(2));
+        assert!(room.is_empty());
+    }
+}
@@ -1,53 +1,104 @@
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
 
 use axum::extract::{Multipart, State};
-use axum::http::Method;
+use axum::http::{Method, StatusCode};
+use axum::middleware::from_fn_with_state;
 use axum::Router;
-use axum::routing::post;
+use axum::routing::{get, post};
+use metrics::counter;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::{error, info, info_span, warn, Instrument};
 
-use shared::{SystemMessages};
+use crate::admin;
+use crate::game_loop::GameLoop;
+use crate::telemetry::WEBHOOK_FAILURES;
+use crate::websocket::{self, Gateway};
 
-use crate::manager::Manager;
-use crate::world::World;
+#[derive(Clone)]
+struct ApiState {
+    game: GameLoop,
+    metrics: PrometheusHandle,
+}
 
-pub async fn build_server(manager: Manager, world: World) {
-    let app = Router::new()
+pub async fn build_server(
+    listener: TcpListener,
+    game: GameLoop,
+    metrics: PrometheusHandle,
+    admin_token: Option<String>,
+    gateway: Gateway,
+    shutdown: impl Future<Output=()> + Send + 'static,
+) {
+    let mut app = Router::new()
         .nest_service("/assets", tower_http::services::ServeDir::new("assets"))
         .route("/generation", post(handle))
-        .with_state((manager, world))
+        .merge(websocket::router(gateway));
+
+    // Metrics give away who is online and how busy the server is, they are for operators only
+    match admin_token {
+        Some(token) => {
+            app = app
+                .route("/metrics", get(render_metrics).layer(from_fn_with_state(token.clone(), admin::authorize)))
+                .nest("/admin", admin::router(game.clone(), token))
+        }
+        None => warn!("ADMIN_TOKEN is not set, the admin api and metrics are disabled"),
+    }
+
+    let app = app
+        .with_state(ApiState { game, metrics })
         .layer(
             CorsLayer::new()
                 .allow_methods([Method::GET, Method::POST])
                 .allow_origin(Any)
         );
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    if let Ok(address) = listener.local_addr() {
+        info!("api server starting at http://{}", address);
+    }
 
-    println!("api server starting at {}", "http://0.0.0.0:3000");
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
+        .await
+        .unwrap();
+}
 
-    axum::serve(listener, app).await.unwrap();
+async fn render_metrics(State(state): State<ApiState>) -> String {
+    state.metrics.render()
 }
 
-async fn handle(State((manager, world)): State<(Manager, World)>, multipart: Multipart) {
-    let (id, asset) = handle_payload(multipart).await.unwrap();
+async fn handle(State(state): State<ApiState>, multipart: Multipart) -> StatusCode {
+    let result = handle_payload(multipart)
+        .instrument(info_span!("webhook"))
+        .await;
 
-    world.complete_monument(id, &asset).await;
-    manager.broadcast(SystemMessages::MonumentCompleted { id, asset }).await;
+    match result {
+        Ok((id, asset)) => {
+            state.game.complete_monument(id, asset);
+            StatusCode::OK
+        }
+        Err(error) => {
+            error!(%error, "failed to process generation webhook");
+            counter!(WEBHOOK_FAILURES).increment(1);
+            StatusCode::BAD_REQUEST
+        }
+    }
 }
 
-async fn handle_payload(mut multipart: Multipart) -> Result<(u32, String), Box<dyn std::error::Error>> {
+async fn handle_payload(mut multipart: Multipart) -> Result<(u32, String), Box<dyn std::error::Error + Send + Sync>> {
     let mut map: HashMap<String, String> = HashMap::new();
 
-    while let Some(mut field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        let data = field.bytes().await.unwrap();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        let data = field.bytes().await?;
 
         match name.as_str() {
             name if name == "file" => {
                 let id = uuid::Uuid::new_v4();
-                tokio::fs::create_dir_all("./assets/monuments").await.unwrap();
-                tokio::fs::write(format!("./assets/monuments/{}.png", id), data).await.unwrap();
+                tokio::fs::create_dir_all("./assets/monuments").await?;
+                tokio::fs::write(format!("./assets/monuments/{}.png", id), data).await?;
                 map.insert(name.to_string(), format!("{}/assets/monuments/{}.png", env!("API_SERVER_ADDRESS"), id));
             }
             name if name == "prompt_id" => {
@@ -58,7 +109,7 @@ async fn handle_payload(mut multipart: Multipart) -> Result<(u32, String), Box<d
     }
 
     Ok((
-        map.get("prompt_id").unwrap().parse::<u32>().unwrap(),
-        map.get("file").unwrap().to_string(),
+        map.get("prompt_id").ok_or("missing prompt_id field")?.parse::<u32>()?,
+        map.get("file").ok_or("missing file field")?.to_string(),
     ))
 }
@@ -0,0 +1,81 @@
+/// Longer messages are cut, in characters
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+/// How far, in tiles, proximity messages are heard
+pub const PROXIMITY_RADIUS: f32 = 20.0;
+
+/// Words masked out of chat messages, matched as whole words regardless of their case
+const PROFANITIES: &[&str] = &[
+    "arse", "arsehole", "ass", "asshole", "bastard", "bitch", "bollocks", "cock", "cunt", "dick",
+    "fag", "faggot", "fuck", "fucker", "fucking", "motherfucker", "nigga", "nigger", "piss",
+    "prick", "pussy", "retard", "shit", "slut", "twat", "wanker", "whore",
+];
+
+/// Strip control characters, collapse whitespace, cut the message to [`MAX_CHAT_LENGTH`] and mask
+/// profanities. `None` when nothing is left to say
+pub fn sanitize(text: &str) -> Option<String> {
+    let text = text
+        .split_whitespace()
+        .map(|word| word.chars().filter(|character| character.is_control() == false).collect::<String>())
+        .filter(|word| word.is_empty() == false)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let text: String = text.chars().take(MAX_CHAT_LENGTH).collect();
+    let text = text.trim_end();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(censor(text))
+    }
+}
+
+/// Replace every letter of a profanity with an asterisk
+fn censor(text: &str) -> String {
+    let mut censored = String::with_capacity(text.len());
+    let mut word = String::new();
+
+    for character in text.chars() {
+        if character.is_alphanumeric() {
+            word.push(character);
+        } else {
+            censored.push_str(&mask(&word));
+            censored.push(character);
+            word.clear();
+        }
+    }
+
+    censored.push_str(&mask(&word));
+    censored
+}
+
+fn mask(word: &str) -> String {
+    if PROFANITIES.contains(&word.to_lowercase().as_str()) {
+        "*".repeat(word.chars().count())
+    } else {
+        word.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_profanities_as_whole_words() {
+        assert_eq!(sanitize("what the Fuck, that's shit!"), Some("what the ****, that's ****!".to_string()));
+        assert_eq!(sanitize("a classic assessment"), Some("a classic assessment".to_string()));
+    }
+
+    #[test]
+    fn cuts_long_messages_and_collapses_whitespace() {
+        assert_eq!(sanitize("  hello \n\t  world "), Some("hello world".to_string()));
+        assert_eq!(sanitize(&"a".repeat(MAX_CHAT_LENGTH * 2)).map(|text| text.len()), Some(MAX_CHAT_LENGTH));
+    }
+
+    #[test]
+    fn ignores_empty_messages() {
+        assert_eq!(sanitize(" \u{7} \n "), None);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+
+use shared::{ChunkId, Coordinate, PlayerId};
+
+/// Chunks loaded by every player, and the players watching every chunk
+#[derive(Default)]
+pub struct ChunkStreamer {
+    loaded: HashMap<PlayerId, HashSet<ChunkId>>,
+    watchers: HashMap<ChunkId, HashSet<PlayerId>>,
+}
+
+#[derive(Debug, Default)]
+pub struct ChunkChanges {
+    /// Came into view, their monuments need to be sent
+    pub entered: Vec<ChunkId>,
+    /// Went out of view, the player can let go of them
+    pub left: Vec<ChunkId>,
+}
+
+impl ChunkStreamer {
+    /// Move the player's view around `position`
+    pub fn update(&mut self, id: PlayerId, position: Coordinate) -> ChunkChanges {
+        let visible: HashSet<_> = position.chunk().neighborhood().collect();
+        let loaded = self.loaded.entry(id).or_default();
+
+        if *loaded == visible {
+            return ChunkChanges::default();
+        }
+
+        let changes = ChunkChanges {
+            entered: visible.difference(loaded).copied().collect(),
+            left: loaded.difference(&visible).copied().collect(),
+        };
+
+        for chunk in &changes.left {
+            if let Some(watchers) = self.watchers.get_mut(chunk) {
+                watchers.remove(&id);
+
+                if watchers.is_empty() {
+                    self.watchers.remove(chunk);
+                }
+            }
+        }
+
+        for chunk in &changes.entered {
+            self.watchers.entry(*chunk).or_default().insert(id);
+        }
+
+        *loaded = visible;
+        changes
+    }
+
+    pub fn remove(&mut self, id: PlayerId) {
+        for chunk in self.loaded.remove(&id).unwrap_or_default() {
+            if let Some(watchers) = self.watchers.get_mut(&chunk) {
+                watchers.remove(&id);
+
+                if watchers.is_empty() {
+                    self.watchers.remove(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Players with the chunk loaded
+    pub fn watchers(&self, chunk: ChunkId) -> HashSet<PlayerId> {
+        self.watchers.get(&chunk).cloned().unwrap_or_default()
+    }
+}
+
+/// Pairs of players close enough to see each other. Views are square and centered on the chunk, so
+/// the relation goes both ways
+#[derive(Default)]
+pub struct Interest {
+    in_view: HashMap<PlayerId, HashSet<PlayerId>>,
+}
+
+impl Interest {
+    /// Replace the players in view of `id` with `visible`, returns who came into view and who left it
+    pub fn update(&mut self, id: PlayerId, mut visible: HashSet<PlayerId>) -> (Vec<PlayerId>, Vec<PlayerId>) {
+        visible.remove(&id);
+
+        let in_view = self.in_view.remove(&id).unwrap_or_default();
+        let entered: Vec<_> = visible.difference(&in_view).copied().collect();
+        let left: Vec<_> = in_view.difference(&visible).copied().collect();
+
+        for other in &entered {
+            self.in_view.entry(*other).or_default().insert(id);
+        }
+
+        for other in &left {
+            if let Some(others) = self.in_view.get_mut(other) {
+                others.remove(&id);
+            }
+        }
+
+        self.in_view.insert(id, visible);
+        (entered, left)
+    }
+
+    pub fn in_view(&self, id: PlayerId) -> HashSet<PlayerId> {
+        self.in_view.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Forget the player, returns who could still see them
+    pub fn remove(&mut self, id: PlayerId) -> HashSet<PlayerId> {
+        let in_view = self.in_view.remove(&id).unwrap_or_default();
+
+        for other in &in_view {
+            if let Some(others) = self.in_view.get_mut(other) {
+                others.remove(&id);
+            }
+        }
+
+        in_view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{CHUNK_SIZE, VIEW_DISTANCE};
+
+    use super::*;
+
+    #[test]
+    fn streams_chunks_coming_into_view() {
+        let mut streamer = ChunkStreamer::default();
+        let id = PlayerId::from(1);
+
+        let changes = streamer.update(id, Coordinate::default());
+        let side = (VIEW_DISTANCE * 2 + 1) as usize;
+
+        assert_eq!(changes.entered.len(), side * side);
+        assert!(changes.left.is_empty());
+
+        // Moving within the same chunk changes nothing
+        let changes = streamer.update(id, Coordinate { x: 1, y: 1 });
+
+        assert!(changes.entered.is_empty() && changes.left.is_empty());
+
+        let changes = streamer.update(id, Coordinate { x: CHUNK_SIZE, y: 0 });
+
+        assert_eq!(changes.entered.len(), side);
+        assert_eq!(changes.left.len(), side);
+        assert!(changes.left.iter().all(|chunk| chunk.x == -VIEW_DISTANCE));
+    }
+
+    #[test]
+    fn tracks_who_watches_each_chunk() {
+        let mut streamer = ChunkStreamer::default();
+        let (alice, bob) = (PlayerId::from(1), PlayerId::from(2));
+
+        streamer.update(alice, Coordinate::default());
+        streamer.update(bob, Coordinate { x: CHUNK_SIZE * 10, y: 0 });
+
+        assert_eq!(streamer.watchers(Coordinate::default().chunk()), HashSet::from([alice]));
+
+        streamer.remove(alice);
+
+        assert!(streamer.watchers(Coordinate::default().chunk()).is_empty());
+    }
+
+    #[test]
+    fn players_see_each_other_both_ways() {
+        let mut interest = Interest::default();
+        let (alice, bob) = (PlayerId::from(1), PlayerId::from(2));
+
+        let (entered, left) = interest.update(alice, HashSet::from([alice, bob]));
+
+        assert_eq!((entered, left), (vec![bob], vec![]));
+        assert_eq!(interest.in_view(bob), HashSet::from([alice]));
+
+        let (entered, left) = interest.update(bob, HashSet::from([bob]));
+
+        assert_eq!((entered, left), (vec![], vec![alice]));
+        assert!(interest.in_view(alice).is_empty());
+
+        interest.update(alice, HashSet::from([bob]));
+
+        assert_eq!(interest.remove(bob), HashSet::from([alice]));
+        assert!(interest.in_view(alice).is_empty());
+    }
+}
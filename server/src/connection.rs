@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use futures_util::{Sink, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use metrics::counter;
+use tokio::time::Instant;
+use tracing::{debug, info, info_span, Instrument};
+
+use shared::{PlayerId, SystemMessages};
+
+use crate::game_loop::GameLoop;
+use crate::outbox;
+use crate::rate_limit::{RateLimiter, Verdict};
+use crate::telemetry::RATE_LIMITED;
+
+/// How often the server pings every client at the websocket level
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Players that haven't been heard from for this long are considered gone
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Writes that take longer than this mean the client stopped reading, the connection is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many messages may be waiting for a single client before it gets evicted
+const OUTBOX_CAPACITY: usize = 512;
+
+/// Drive a single player's connection until either side goes away
+pub async fn handle<S>(websocket: WebSocketStream<S>, game: GameLoop)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let player_id = PlayerId::random();
+
+    serve(websocket, game, player_id)
+        .instrument(info_span!("connection", player = ?player_id))
+        .await
+}
+
+async fn serve<S>(websocket: WebSocketStream<S>, game: GameLoop, player_id: PlayerId)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut websocket_writer, mut websocket_reader) = websocket.split();
+
+    let (sender, mut receiver) = outbox::channel(OUTBOX_CAPACITY);
+
+    game.connect(player_id, sender);
+
+    info!("player connected");
+
+    // Task: send to client
+    let mut write_task = tokio::spawn(async move {
+        let mut close_code = CloseCode::Normal;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+
+                    if let Some(code) = close_code_for(&message) {
+                        close_code = code;
+                    }
+
+                    if !send(&mut websocket_writer, message.into()).await {
+                        return;
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    if !send(&mut websocket_writer, Message::Ping(Default::default())).await {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let frame = CloseFrame { code: close_code, reason: "".into() };
+        let _ = send(&mut websocket_writer, Message::Close(Some(frame))).await;
+    }.in_current_span());
+
+    // Task: receive from client
+    let reader_game = game.clone();
+
+    let mut read_task = tokio::spawn(async move {
+        let mut limiter = RateLimiter::default();
+
+        while let Some(Ok(message)) = websocket_reader.next().await {
+            // Any frame counts as a sign of life, including the pong replies to our pings
+            let Ok(message) = SystemMessages::try_from(message) else {
+                reader_game.touch(player_id);
+                continue;
+            };
+
+            // Throttled here so a flood never even reaches the game loop
+            let verdict = limiter.check(&message, Instant::now());
+
+            if verdict != Verdict::Allow {
+                counter!(RATE_LIMITED, "kind" => message.kind()).increment(1);
+            }
+
+            match verdict {
+                Verdict::Allow => reader_game.message(player_id, message),
+                Verdict::Drop { notify } => {
+                    debug!(kind = message.kind(), "rate limited");
+                    reader_game.touch(player_id);
+
+                    if let Some(retry_after) = notify {
+                        reader_game.rate_limited(player_id, message.kind(), retry_after);
+                    }
+                }
+                Verdict::Disconnect => {
+                    reader_game.evict(player_id, format!("sent too many {} messages", message.kind()));
+                    break;
+                }
+            }
+        }
+    }.in_current_span());
+
+    // The writer ends first whenever the player gets disconnected by the server
+    tokio::select! {
+        _ = &mut write_task => read_task.abort(),
+        _ = &mut read_task => {}
+    }
+
+    info!("player disconnected");
+
+    game.disconnect(player_id);
+
+    let _ = write_task.await;
+}
+
+/// A client that stopped reading fills up its socket buffer and would block the writer forever,
+/// so give up on it after a while. Returns whether the message was written
+async fn send<W>(writer: &mut W, message: Message) -> bool
+where
+    W: Sink<Message> + Unpin,
+{
+    matches!(tokio::time::timeout(WRITE_TIMEOUT, writer.send(message)).await, Ok(Ok(())))
+}
+
+/// Messages that end the session determine the close code sent along with the close frame
+fn close_code_for(message: &SystemMessages) -> Option<CloseCode> {
+    match message {
+        SystemMessages::ServerShuttingDown { .. } => Some(CloseCode::Away),
+        SystemMessages::Evicted { .. } => Some(CloseCode::Policy),
+        _ => None,
+    }
+}
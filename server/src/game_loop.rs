@@ -0,0 +1,832 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use metrics::{counter, gauge, histogram};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+use shared::{ChatChannel, ChunkId, Coordinate, MAX_MOVE_DISTANCE, MAX_PLACEMENT_DISTANCE, MONUMENT_PRICE, Cosmetics, Monument, PlayerData, PlayerId, SystemMessages, ROBOT_SPEED};
+
+use crate::admission::{Capacity, WaitingRoom};
+use crate::chat::{self, PROXIMITY_RADIUS};
+use crate::chunks::{ChunkStreamer, Interest};
+use crate::connection::{HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT};
+use crate::generator::ImageGenerator;
+use crate::manager::Manager;
+use crate::names;
+use crate::outbox::Sender;
+use crate::telemetry::{
+    COMFYUI_REQUEST_DURATION, GENERATION_DURATION, GENERATION_FAILURES, GENERATION_QUEUE_DEPTH, MESSAGES_RECEIVED,
+    TICK_DURATION, WAITING_PLAYERS,
+};
+use crate::world::{MonumentCache, World};
+
+/// How often queued commands are processed and movement is fanned out to everyone
+pub const TICK: Duration = Duration::from_millis(50);
+
+/// Sent to players removed through the admin API
+const KICK_REASON: &str = "kicked by an administrator";
+
+/// Sent to players turned away because both the world and the waiting room are full
+const SERVER_FULL_REASON: &str = "the server is full, try again later";
+
+const NOT_ENOUGH_TOKENS_REASON: &str = "not enough tokens";
+const TOO_FAR_REASON: &str = "too far away to build there";
+const OCCUPIED_REASON: &str = "there is already a monument there";
+const GENERATION_FAILED_REASON: &str = "the monument could not be generated, try again later";
+
+/// A monument still waiting for its generation webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingGeneration {
+    pub id: u32,
+    pub prompt: String,
+    pub position: Coordinate,
+    /// Seconds since the generation was queued, unknown for generations queued before a restart
+    pub waiting_for: Option<f64>,
+}
+
+enum Command {
+    Connect { id: PlayerId, sender: Sender },
+    Disconnect { id: PlayerId },
+    Message { id: PlayerId, message: SystemMessages },
+    Touch { id: PlayerId },
+    RateLimited { id: PlayerId, kind: &'static str, retry_after: Duration },
+    Evict { id: PlayerId, reason: String },
+    GenerationQueued { monument: Monument },
+    GenerationFailed { id: PlayerId, coordinate: Coordinate },
+    CompleteMonument { id: u32, asset: String },
+    PendingGenerations { reply: oneshot::Sender<usize> },
+    Shutdown { message: SystemMessages, reply: oneshot::Sender<usize> },
+    Flush { reply: oneshot::Sender<std::io::Result<()>> },
+    Players { reply: oneshot::Sender<Vec<PlayerData>> },
+    Kick { id: PlayerId, reply: oneshot::Sender<bool> },
+    AdjustBalance { id: PlayerId, amount: i64, reply: oneshot::Sender<Option<u32>> },
+    RemoveMonument { id: u32, reply: oneshot::Sender<bool> },
+    SetMonumentHidden { id: u32, hidden: bool, reply: oneshot::Sender<bool> },
+    Generations { reply: oneshot::Sender<Vec<PendingGeneration>> },
+}
+
+/// Handle to the game loop, the single task that owns every player, monument and connection.
+///
+/// Commands are queued and processed in batches once per [`TICK`], so nothing ever contends on a lock.
+#[derive(Clone)]
+pub struct GameLoop {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl GameLoop {
+    pub fn spawn(world: World, capacity: Capacity, generator: impl ImageGenerator) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+
+        let state = State {
+            world,
+            generator: Arc::new(generator),
+            cache: MonumentCache::spawn(),
+            manager: Manager::default(),
+            capacity,
+            waiting_room: WaitingRoom::default(),
+            shutdown: None,
+            commands: commands.downgrade(),
+            movements: HashMap::new(),
+            generations: HashMap::new(),
+            chunks: ChunkStreamer::default(),
+            interest: Interest::default(),
+        };
+
+        tokio::spawn(state.run(receiver));
+
+        Self { commands }
+    }
+
+    pub fn connect(&self, id: PlayerId, sender: Sender) {
+        self.send(Command::Connect { id, sender })
+    }
+
+    pub fn disconnect(&self, id: PlayerId) {
+        self.send(Command::Disconnect { id })
+    }
+
+    pub fn message(&self, id: PlayerId, message: SystemMessages) {
+        self.send(Command::Message { id, message })
+    }
+
+    pub fn touch(&self, id: PlayerId) {
+        self.send(Command::Touch { id })
+    }
+
+    /// Let the player know their messages of this kind are being dropped
+    pub fn rate_limited(&self, id: PlayerId, kind: &'static str, retry_after: Duration) {
+        self.send(Command::RateLimited { id, kind, retry_after })
+    }
+
+    /// Close the player's connection, the reason is sent along before closing
+    pub fn evict(&self, id: PlayerId, reason: String) {
+        self.send(Command::Evict { id, reason })
+    }
+
+    pub fn complete_monument(&self, id: u32, asset: String) {
+        self.send(Command::CompleteMonument { id, asset })
+    }
+
+    pub async fn pending_generations(&self) -> usize {
+        self.request(|reply| Command::PendingGenerations { reply }).await.unwrap_or_default()
+    }
+
+    /// Wait until every pending generation has been completed or the timeout expires,
+    /// returns the number of generations that were still pending when giving up
+    pub async fn wait_for_pending_generations(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let pending = self.pending_generations().await;
+
+            if pending == 0 || Instant::now() >= deadline {
+                return pending;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Deliver a final message to every player and close their connections
+    pub async fn shutdown(&self, message: SystemMessages) -> usize {
+        self.request(|reply| Command::Shutdown { message, reply }).await.unwrap_or_default()
+    }
+
+    /// Persist every completed monument
+    pub async fn flush(&self) -> std::io::Result<()> {
+        self.request(|reply| Command::Flush { reply })
+            .await
+            .unwrap_or_else(|| Err(std::io::Error::other("game loop is gone")))
+    }
+
+    pub async fn players(&self) -> Vec<PlayerData> {
+        self.request(|reply| Command::Players { reply }).await.unwrap_or_default()
+    }
+
+    /// Disconnect a player, returns false if no such player is connected
+    pub async fn kick(&self, id: PlayerId) -> bool {
+        self.request(|reply| Command::Kick { id, reply }).await.unwrap_or_default()
+    }
+
+    /// Add (or subtract) tokens from a player, returns the new balance
+    pub async fn adjust_balance(&self, id: PlayerId, amount: i64) -> Option<u32> {
+        self.request(|reply| Command::AdjustBalance { id, amount, reply }).await.flatten()
+    }
+
+    /// Delete a monument for good and remove it from every player's world
+    pub async fn remove_monument(&self, id: u32) -> bool {
+        self.request(|reply| Command::RemoveMonument { id, reply }).await.unwrap_or_default()
+    }
+
+    /// Hide (or show again) a monument without deleting it
+    pub async fn set_monument_hidden(&self, id: u32, hidden: bool) -> bool {
+        self.request(|reply| Command::SetMonumentHidden { id, hidden, reply }).await.unwrap_or_default()
+    }
+
+    pub async fn generations(&self) -> Vec<PendingGeneration> {
+        self.request(|reply| Command::Generations { reply }).await.unwrap_or_default()
+    }
+
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Option<T> {
+        let (reply, response) = oneshot::channel();
+        self.send(command(reply));
+        response.await.ok()
+    }
+}
+
+struct State<G> {
+    world: World,
+    generator: Arc<G>,
+    /// Every write to the monument cache goes through here, in order
+    cache: MonumentCache,
+    manager: Manager,
+    capacity: Capacity,
+    /// Players connected but not yet admitted into the world
+    waiting_room: WaitingRoom,
+    /// Final message of the shutdown, sent to anyone still trying to connect afterward
+    shutdown: Option<SystemMessages>,
+    /// Used by background jobs to report back, weak so the loop ends once every handle is gone
+    commands: mpsc::WeakUnboundedSender<Command>,
+    /// Latest position of every player that moved during the current tick
+    movements: HashMap<PlayerId, (Coordinate, u64)>,
+    /// When each pending generation was queued
+    generations: HashMap<u32, Instant>,
+    /// Chunks of the world each player was sent
+    chunks: ChunkStreamer,
+    /// Robots each player was spawned, only those are kept up to date
+    interest: Interest,
+}
+
+impl<G: ImageGenerator> State<G> {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<Command>) {
+        let mut tick = tokio::time::interval(TICK);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {
+                    let started = Instant::now();
+
+                    loop {
+                        match receiver.try_recv() {
+                            Ok(command) => {
+                                self.handle(command);
+                                self.forget_dropped_players();
+                            }
+                            Err(mpsc::error::TryRecvError::Empty) => break,
+                            Err(mpsc::error::TryRecvError::Disconnected) => return,
+                        }
+                    }
+
+                    self.broadcast_movements();
+                    self.forget_dropped_players();
+
+                    histogram!(TICK_DURATION).record(started.elapsed().as_secs_f64());
+                }
+                _ = heartbeat.tick() => {
+                    self.disconnect_unresponsive_players();
+                    self.forget_dropped_players();
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Connect { id, sender } => self.admit_or_queue(id, sender),
+            Command::Disconnect { id } => {
+                if self.waiting_room.leave(id) {
+                    self.record_waiting_players();
+                } else {
+                    self.on_player_disconnect(id);
+                    self.admit_waiting_players();
+                }
+            }
+            Command::Message { id, message } => {
+                // Players in the waiting room have nothing to interact with yet
+                if self.manager.contains(id) {
+                    self.manager.touch(id);
+                    self.handle_player_communication(id, message);
+                }
+            }
+            Command::Touch { id } => self.manager.touch(id),
+            Command::RateLimited { id, kind, retry_after } => {
+                let retry_after_ms = retry_after.as_millis().min(u32::MAX as u128) as u32;
+                self.manager.broadcast_to(id, SystemMessages::RateLimited { kind: kind.into(), retry_after_ms });
+            }
+            Command::Evict { id, reason } => {
+                warn!(player = ?id, reason, "evicting player");
+
+                if self.manager.kick(id, &reason) {
+                    self.on_player_disconnect(id);
+                    self.admit_waiting_players();
+                }
+            }
+            Command::GenerationQueued { monument } => {
+                self.generations.insert(monument.id, Instant::now());
+                self.world.add_monument(monument.clone());
+                self.record_generation_queue_depth();
+
+                self.broadcast_to_chunk(monument.position.chunk(), SystemMessages::BuildMonument { monument });
+            }
+            Command::GenerationFailed { id, coordinate } => {
+                self.world.release_placement(coordinate);
+
+                if let Some(balance) = self.world.adjust_balance(id, MONUMENT_PRICE as i64) {
+                    self.manager.broadcast_to(id, SystemMessages::MainPlayerCurrentBalance { balance });
+                }
+
+                self.manager.broadcast_to(id, SystemMessages::BuildMonumentRejected { reason: GENERATION_FAILED_REASON.into() });
+            }
+            Command::CompleteMonument { id, asset } => {
+                if let Some(monument) = self.world.complete_monument(id, &asset) {
+                    let monument = monument.clone();
+                    let hidden = monument.hidden;
+                    let chunk = monument.position.chunk();
+
+                    self.cache.append(monument);
+
+                    if let Some(queued) = self.generations.remove(&id) {
+                        histogram!(GENERATION_DURATION).record(queued.elapsed().as_secs_f64());
+                    }
+
+                    info!(monument = id, "monument completed");
+
+                    self.record_generation_queue_depth();
+
+                    if hidden == false {
+                        self.broadcast_to_chunk(chunk, SystemMessages::MonumentCompleted { id, asset });
+                    }
+                } else {
+                    warn!(monument = id, "completed a monument that doesn't exist");
+                }
+            }
+            Command::PendingGenerations { reply } => {
+                let _ = reply.send(self.world.pending_generations());
+            }
+            Command::Shutdown { message, reply } => {
+                let waiting = self.waiting_room.shutdown(message.clone());
+                self.record_waiting_players();
+                self.shutdown = Some(message.clone());
+
+                let _ = reply.send(self.manager.shutdown(message) + waiting);
+            }
+            Command::Flush { reply } => {
+                let flushed = self.cache.flush(self.world.monuments().cloned().collect());
+
+                tokio::spawn(async move {
+                    let _ = reply.send(flushed.await);
+                });
+            }
+            Command::Players { reply } => {
+                let _ = reply.send(self.world.players().cloned().collect());
+            }
+            Command::Kick { id, reply } => {
+                info!(player = ?id, "kicking player");
+                let kicked = self.manager.kick(id, KICK_REASON);
+
+                // Free the slot right away rather than once the connection notices
+                if kicked {
+                    self.on_player_disconnect(id);
+                    self.admit_waiting_players();
+                }
+
+                let _ = reply.send(kicked);
+            }
+            Command::AdjustBalance { id, amount, reply } => {
+                let balance = self.world.adjust_balance(id, amount);
+
+                if let Some(balance) = balance {
+                    self.manager.broadcast_to(id, SystemMessages::MainPlayerCurrentBalance { balance });
+                }
+
+                let _ = reply.send(balance);
+            }
+            Command::RemoveMonument { id, reply } => {
+                let removed = self.world.remove_monument(id);
+
+                if let Some(monument) = &removed {
+                    info!(monument = id, "monument removed");
+
+                    self.generations.remove(&id);
+                    self.record_generation_queue_depth();
+                    self.persist_monuments();
+                    self.broadcast_to_chunk(monument.position.chunk(), SystemMessages::MonumentRemoved { id });
+                }
+
+                let _ = reply.send(removed.is_some());
+            }
+            Command::SetMonumentHidden { id, hidden, reply } => {
+                let Some(monument) = self.world.set_monument_hidden(id, hidden).cloned() else {
+                    let _ = reply.send(false);
+                    return;
+                };
+
+                info!(monument = id, hidden, "monument visibility changed");
+
+                self.persist_monuments();
+
+                let chunk = monument.position.chunk();
+
+                if hidden {
+                    self.broadcast_to_chunk(chunk, SystemMessages::MonumentRemoved { id });
+                } else {
+                    self.broadcast_to_chunk(chunk, SystemMessages::BuildMonument { monument });
+                }
+
+                let _ = reply.send(true);
+            }
+            Command::Generations { reply } => {
+                let generations = self.world
+                    .monuments()
+                    .filter(|monument| monument.under_construction)
+                    .map(|monument| PendingGeneration {
+                        id: monument.id,
+                        prompt: monument.description.clone(),
+                        position: monument.position,
+                        waiting_for: self.generations.get(&monument.id).map(|queued| queued.elapsed().as_secs_f64()),
+                    })
+                    .collect();
+
+                let _ = reply.send(generations);
+            }
+        }
+    }
+
+    /// The cache is append only, so rewrite it whenever a monument changes after being completed
+    fn persist_monuments(&self) {
+        self.cache.rewrite(self.world.monuments().cloned().collect());
+    }
+
+    fn handle_player_communication(&mut self, id: PlayerId, message: SystemMessages) {
+        counter!(MESSAGES_RECEIVED, "kind" => message.kind()).increment(1);
+
+        match message {
+            SystemMessages::PlayerPosition { .. } => trace!(player = ?id, ?message, "received"),
+            _ => debug!(player = ?id, ?message, "received"),
+        }
+
+        match message {
+            SystemMessages::Ping => self.manager.broadcast_to(id, SystemMessages::Pong),
+            SystemMessages::PlayerPosition { coordinate, sequence } => {
+                let Some(position) = self.world.get(id).map(|data| data.position) else {
+                    return;
+                };
+
+                // Further than anyone could click, walking through monuments, or somewhere the robot
+                // can't get to. The distance is checked first so bogus moves never reach the pathfinder
+                if position.distance(&coordinate) > MAX_MOVE_DISTANCE || !self.world.is_reachable(position, coordinate) {
+                    debug!(player = ?id, ?coordinate, "move rejected");
+                    self.manager.broadcast_to(id, SystemMessages::MoveAcknowledged { sequence, coordinate: position });
+                    return;
+                }
+
+                self.world.update_coordinate(id, coordinate);
+                self.movements.insert(id, (coordinate, since_epoch().as_millis() as u64));
+                self.stream_chunks(id, coordinate, false);
+                self.manager.broadcast_to(id, SystemMessages::MoveAcknowledged { sequence, coordinate });
+            }
+            SystemMessages::BuildMonumentRequest { prompt, coordinate } => {
+                if let Some(data) = self.world.get(id) {
+                    let rejection = if data.balance < MONUMENT_PRICE {
+                        Some(NOT_ENOUGH_TOKENS_REASON)
+                    } else if data.position.distance(&coordinate) > MAX_PLACEMENT_DISTANCE {
+                        Some(TOO_FAR_REASON)
+                    } else {
+                        None
+                    };
+
+                    let owner_name = data.name.clone();
+                    let created_at = since_epoch().as_secs();
+
+                    if let Some(reason) = rejection {
+                        debug!(player = ?id, reason, "monument request rejected");
+                        self.manager.broadcast_to(id, SystemMessages::BuildMonumentRejected { reason: reason.into() });
+                        return;
+                    }
+
+                    let Some(commands) = self.commands.upgrade() else {
+                        return;
+                    };
+
+                    // Claimed right away so concurrent requests can't pick the same spot
+                    if self.world.reserve_placement(coordinate) == false {
+                        self.manager.broadcast_to(id, SystemMessages::BuildMonumentRejected { reason: OCCUPIED_REASON.into() });
+                        return;
+                    }
+
+                    // Paid up front so back to back requests can't both pass the balance check,
+                    // refunded if the generation can't be queued
+                    let Some(balance) = self.world.spend(id, MONUMENT_PRICE) else {
+                        self.world.release_placement(coordinate);
+                        self.manager.broadcast_to(id, SystemMessages::BuildMonumentRejected { reason: NOT_ENOUGH_TOKENS_REASON.into() });
+                        return;
+                    };
+
+                    self.manager.broadcast_to(id, SystemMessages::MainPlayerCurrentBalance { balance });
+
+                    let generator = self.generator.clone();
+                    let span = info_span!("generation", player = ?id, prompt = %prompt);
+
+                    tokio::spawn(async move {
+                        let started = Instant::now();
+                        let result = generator.generate(prompt.as_str()).await;
+
+                        histogram!(COMFYUI_REQUEST_DURATION).record(started.elapsed().as_secs_f64());
+
+                        match result {
+                            Ok(monument_id) => {
+                                info!(monument = monument_id, "generation queued");
+
+                                let monument = Monument {
+                                    id: monument_id,
+                                    description: prompt,
+                                    asset: "under-construction.png".into(),
+                                    position: coordinate,
+                                    under_construction: true,
+                                    hidden: false,
+                                    owner: Some(id),
+                                    owner_name,
+                                    created_at,
+                                };
+
+                                let _ = commands.send(Command::GenerationQueued { monument });
+                            }
+                            Err(error) => {
+                                error!(?error, "failed to queue generation");
+                                counter!(GENERATION_FAILURES).increment(1);
+                                let _ = commands.send(Command::GenerationFailed { id, coordinate });
+                            }
+                        }
+                    }.instrument(span));
+                }
+            }
+            SystemMessages::ChangeName { name } => {
+                let renamed = names::normalize(&name).and_then(|name| {
+                    self.world.rename(id, name.clone())?;
+                    Ok(name)
+                });
+
+                match renamed {
+                    Ok(name) => {
+                        info!(player = ?id, name, "player renamed");
+                        self.broadcast_around(id, SystemMessages::PlayerRenamed { id, name });
+                    }
+                    Err(error) => {
+                        self.manager.broadcast_to(id, SystemMessages::NameRejected { reason: error.to_string() });
+                    }
+                }
+            }
+            SystemMessages::ChangeCosmetics { cosmetics } => {
+                match self.world.change_cosmetics(id, cosmetics) {
+                    Some(balance) => {
+                        info!(player = ?id, ?cosmetics, "player changed cosmetics");
+                        self.manager.broadcast_to(id, SystemMessages::MainPlayerCurrentBalance { balance });
+                        self.broadcast_around(id, SystemMessages::PlayerCosmeticsChanged { id, cosmetics });
+                    }
+                    None => {
+                        self.manager.broadcast_to(id, SystemMessages::CosmeticsRejected { reason: NOT_ENOUGH_TOKENS_REASON.into() });
+                    }
+                }
+            }
+            SystemMessages::Emote { kind } => {
+                let in_view = self.interest.in_view(id);
+                self.manager.broadcast_among(&in_view, SystemMessages::EnemyEmote { id, kind });
+            }
+            SystemMessages::SendChat { channel, text } => {
+                let Some(text) = chat::sanitize(&text) else {
+                    return;
+                };
+
+                let Some(data) = self.world.get(id) else {
+                    return;
+                };
+
+                let position = data.position;
+                let message = SystemMessages::Chat { id, name: data.name.clone(), channel, text };
+
+                match channel {
+                    ChatChannel::Global => self.manager.broadcast(message),
+                    ChatChannel::Proximity => {
+                        let listeners: HashSet<_> = self.world
+                            .players_near(position, PROXIMITY_RADIUS)
+                            .map(|data| data.id)
+                            .collect();
+
+                        self.manager.broadcast_among(&listeners, message);
+                    }
+                }
+            }
+            SystemMessages::MainPlayerPickedUpToken => {
+                let balance = self.world.increment_balance(id);
+                self.manager.broadcast_to(id, SystemMessages::MainPlayerCurrentBalance { balance });
+            }
+            _ => {}
+        }
+    }
+
+    /// Spawn the player right away if there is room in the world, otherwise queue them up
+    fn admit_or_queue(&mut self, id: PlayerId, sender: Sender) {
+        if let Some(message) = &self.shutdown {
+            sender.close_with(message.clone());
+        } else if self.has_free_slot() && self.waiting_room.is_empty() {
+            self.on_player_connect(id, sender);
+        } else if self.waiting_room.len() < self.capacity.max_waiting {
+            info!(player = ?id, "world is full, player is waiting for a slot");
+            self.waiting_room.join(id, sender);
+            self.record_waiting_players();
+        } else {
+            warn!(player = ?id, "world and waiting room are full, turning player away");
+            sender.close_with(SystemMessages::Evicted { reason: SERVER_FULL_REASON.into() });
+        }
+    }
+
+    /// Players count against the capacity for as long as they are in the world, the world is also
+    /// what decides when they leave it
+    fn has_free_slot(&self) -> bool {
+        self.world.player_count() < self.capacity.max_players
+    }
+
+    fn admit_waiting_players(&mut self) {
+        while self.has_free_slot() {
+            let Some((id, sender)) = self.waiting_room.admit() else {
+                break;
+            };
+
+            info!(player = ?id, "admitting player from the waiting room");
+            self.on_player_connect(id, sender);
+        }
+
+        self.record_waiting_players();
+    }
+
+    fn record_waiting_players(&self) {
+        gauge!(WAITING_PLAYERS).set(self.waiting_room.len() as f64);
+    }
+
+    fn on_player_connect(&mut self, id: PlayerId, sender: Sender) {
+        let data = PlayerData {
+            id,
+            name: self.world.available_default_name(id),
+            balance: 0,
+            position: Coordinate::default(),
+            cosmetics: Cosmetics::default(),
+        };
+
+        let position = data.position;
+
+        self.world.add(data.clone());
+        self.manager.add(id, sender);
+
+        // Spawn the main player
+        self.manager.broadcast_to(id, SystemMessages::MainPlayerSpawn { data });
+
+        // Then notify everyone around that there is a new boss in town
+        self.stream_chunks(id, position, true);
+    }
+
+    /// Send the monuments of the chunks the player just came close to, and let go of the far ones.
+    /// Only the initial sync of a new player may go over the queue capacity, someone who can't keep
+    /// up with the chunks they walk into is dropped like for any other message
+    fn stream_chunks(&mut self, id: PlayerId, position: Coordinate, initial: bool) {
+        let changes = self.chunks.update(id, position);
+
+        if changes.entered.is_empty() && changes.left.is_empty() {
+            return;
+        }
+
+        self.update_view(id, position);
+
+        for chunk in changes.left {
+            self.manager.broadcast_to(id, SystemMessages::ChunkUnloaded { chunk });
+        }
+
+        for chunk in changes.entered {
+            for monument in self.world.monuments_in(chunk).filter(|monument| !monument.hidden) {
+                let message = SystemMessages::BuildMonument { monument: monument.clone() };
+
+                if initial {
+                    self.manager.sync_to(id, message);
+                } else {
+                    self.manager.broadcast_to(id, message);
+                }
+            }
+        }
+    }
+
+    /// Spawn the robots that came into view on both ends, and despawn the ones that left it.
+    /// Views only change when a player crosses into another chunk
+    fn update_view(&mut self, id: PlayerId, position: Coordinate) {
+        let (entered, left) = self.interest.update(id, self.chunks.watchers(position.chunk()));
+
+        for other in entered {
+            if let (Some(data), Some(other_data)) = (self.world.get(id), self.world.get(other)) {
+                self.manager.broadcast_to(id, SystemMessages::EnemyPlayerSpawn { data: other_data.clone() });
+                self.manager.broadcast_to(other, SystemMessages::EnemyPlayerSpawn { data: data.clone() });
+            }
+        }
+
+        for other in left {
+            self.manager.broadcast_to(id, SystemMessages::EnemyOutOfView { id: other });
+            self.manager.broadcast_to(other, SystemMessages::EnemyOutOfView { id });
+        }
+    }
+
+    /// Tell the player and everyone who can see their robot, the others are sent its latest
+    /// state along with the spawn once it comes into view
+    fn broadcast_around(&mut self, id: PlayerId, message: SystemMessages) {
+        let mut audience = self.interest.in_view(id);
+        audience.insert(id);
+
+        self.manager.broadcast_among(&audience, message);
+    }
+
+    /// Only players with the chunk loaded hear about what happens in it
+    fn broadcast_to_chunk(&mut self, chunk: ChunkId, message: SystemMessages) {
+        let watchers = self.chunks.watchers(chunk);
+        self.manager.broadcast_among(&watchers, message);
+    }
+
+    fn record_generation_queue_depth(&self) {
+        gauge!(GENERATION_QUEUE_DEPTH).set(self.world.pending_generations() as f64);
+    }
+
+    fn on_player_disconnect(&mut self, id: PlayerId) {
+        self.manager.remove(id);
+        self.movements.remove(&id);
+        self.chunks.remove(id);
+
+        let in_view = self.interest.remove(id);
+
+        if self.world.remove(id).is_some() {
+            self.manager.broadcast_among(&in_view, SystemMessages::EnemyDisconnected { id });
+        }
+    }
+
+    /// Players evicted for falling behind leave the world right away, freeing their slot
+    fn forget_dropped_players(&mut self) {
+        let mut dropped = self.manager.take_dropped();
+
+        if dropped.is_empty() {
+            return;
+        }
+
+        // Telling everyone around may drop more of them
+        while !dropped.is_empty() {
+            for id in dropped {
+                self.on_player_disconnect(id);
+            }
+
+            dropped = self.manager.take_dropped();
+        }
+
+        self.admit_waiting_players();
+    }
+
+    /// Only the latest position of each player within a tick is worth sending
+    fn broadcast_movements(&mut self) {
+        for (id, (coordinate, timestamp)) in std::mem::take(&mut self.movements) {
+            let in_view = self.interest.in_view(id);
+            self.manager.broadcast_among(&in_view, SystemMessages::EnemyPosition { id, coordinate, timestamp, speed: ROBOT_SPEED });
+        }
+    }
+
+    /// Half-open connections never end on their own, so drop players that stopped answering pings
+    fn disconnect_unresponsive_players(&mut self) {
+        let unresponsive = self.manager.unresponsive(HEARTBEAT_TIMEOUT);
+
+        if unresponsive.is_empty() {
+            return;
+        }
+
+        for id in unresponsive {
+            warn!(player = ?id, "player stopped responding");
+            self.on_player_disconnect(id);
+        }
+
+        self.admit_waiting_players();
+    }
+}
+
+/// Server time, shared with players through timestamps
+fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use shared::{PlayerId, SystemMessages};
+
+    use super::GameLoop;
+    use crate::admission::Capacity;
+    use crate::connection::{HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT};
+    use crate::generator::StubGenerator;
+    use crate::outbox;
+    use crate::world::World;
+
+    #[tokio::test(start_paused = true)]
+    async fn players_that_stop_answering_pings_disconnect_for_everyone_else() {
+        let game = GameLoop::spawn(World::default(), Capacity::default(), StubGenerator::default());
+
+        let (alice, bob) = (PlayerId::from(1), PlayerId::from(2));
+        let (alice_sender, _alice_receiver) = outbox::channel(512);
+        let (bob_sender, mut bob_receiver) = outbox::channel(512);
+
+        game.connect(alice, alice_sender);
+        game.connect(bob, bob_sender);
+
+        // Only bob keeps answering
+        let deadline = Instant::now() + HEARTBEAT_TIMEOUT + HEARTBEAT_INTERVAL * 2;
+
+        while Instant::now() < deadline {
+            tokio::time::sleep(HEARTBEAT_INTERVAL / 2).await;
+            game.touch(bob);
+        }
+
+        let disconnected = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(message) = bob_receiver.recv().await {
+                if let SystemMessages::EnemyDisconnected { id } = message {
+                    return id;
+                }
+            }
+
+            panic!("bob got disconnected too");
+        });
+
+        assert_eq!(disconnected.await.unwrap(), alice);
+    }
+}
@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::comfyui::ComfyUI;
+
+pub type GenerationError = Box<dyn Error + Send + Sync>;
+
+/// Turns prompts into monument images.
+///
+/// Generating only queues the work and returns the id of the monument,
+/// the image itself is delivered later through the `/generation` webhook.
+pub trait ImageGenerator: Send + Sync + 'static {
+    fn generate(&self, prompt: &str) -> impl Future<Output=Result<u32, GenerationError>> + Send;
+}
+
+impl ImageGenerator for ComfyUI {
+    async fn generate(&self, prompt: &str) -> Result<u32, GenerationError> {
+        Ok(ComfyUI::generate(self, prompt).await?)
+    }
+}
+
+/// Queues every prompt instantly without generating anything, for tests and load testing.
+///
+/// Nothing ever calls the webhook, monuments stay under construction unless it's called by hand.
+#[derive(Default)]
+pub struct StubGenerator {
+    next_id: AtomicU32,
+}
+
+impl ImageGenerator for StubGenerator {
+    async fn generate(&self, _prompt: &str) -> Result<u32, GenerationError> {
+        Ok(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+/// Generator picked at startup through `IMAGE_GENERATOR`
+pub enum Generator {
+    ComfyUI(ComfyUI),
+    Stub(StubGenerator),
+}
+
+impl Generator {
+    pub fn from_env() -> Self {
+        match std::env::var("IMAGE_GENERATOR").as_deref() {
+            Ok("stub") => Generator::Stub(StubGenerator::default()),
+            _ => Generator::ComfyUI(ComfyUI::new()),
+        }
+    }
+}
+
+impl ImageGenerator for Generator {
+    async fn generate(&self, prompt: &str) -> Result<u32, GenerationError> {
+        match self {
+            Generator::ComfyUI(generator) => ImageGenerator::generate(generator, prompt).await,
+            Generator::Stub(generator) => generator.generate(prompt).await,
+        }
+    }
+}
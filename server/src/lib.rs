@@ -0,0 +1,17 @@
+pub mod admin;
+pub mod admission;
+pub mod api;
+pub mod chat;
+pub mod chunks;
+pub mod comfyui;
+pub mod connection;
+pub mod game_loop;
+pub mod generator;
+mod manager;
+pub mod names;
+pub mod occupancy;
+pub mod outbox;
+pub mod rate_limit;
+pub mod telemetry;
+pub mod websocket;
+pub mod world;
@@ -1,167 +1,143 @@
 use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
 
-use futures_util::{SinkExt, StreamExt};
-use futures_util::task::SpawnExt;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_tungstenite::accept_async;
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
 
-use manager::{Manager, ScopedManager};
-use shared::{Coordinate, Monument, PlayerData, PlayerId, SystemMessages};
-use world::World;
+use server::admission::{Capacity, ConnectionsPerIp};
+use server::api::build_server;
+use server::generator::Generator;
+use server::game_loop::GameLoop;
+use server::telemetry;
+use server::websocket::{self, Gateway};
+use server::world::World;
+use shared::SystemMessages;
 
-use crate::api::build_server;
-use crate::comfyui::ComfyUI;
+/// How long to wait for in-flight generation webhooks once a shutdown was requested
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
 
-mod comfyui;
-mod api;
-mod world;
-mod manager;
+/// How long to wait for writers to deliver their close frames before exiting
+const CLOSE_FRAMES_TIMEOUT: Duration = Duration::from_secs(5);
 
-type Sender = mpsc::UnboundedSender<SystemMessages>;
+/// Hint sent to clients on how long they should wait before reconnecting (in seconds)
+const RECONNECT_AFTER: u32 = 10;
+
+/// Connections a single address may hold open at once, players behind the same NAT share it
+const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 8;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let listener = TcpListener::bind("0.0.0.0:9001").await?;
-    let manager = Manager::new();
-    let world = World::default();
+    telemetry::init_tracing();
+
+    let metrics = telemetry::install_recorder();
+    let mut world = World::default();
     // let engine = Engine::new().await?;
 
     if let Err(error) = world.restore_monuments_from_cache().await {
-        println!("failed to restore monuments {:?}", error)
+        warn!(?error, "failed to restore monuments")
     };
 
-    let world_clone = world.clone();
+    let defaults = Capacity::default();
 
-    tokio::spawn(build_server(manager.clone(), world.clone()));
+    let capacity = Capacity {
+        max_players: env_or("MAX_PLAYERS", defaults.max_players),
+        max_waiting: env_or("MAX_WAITING_PLAYERS", defaults.max_waiting),
+    };
 
-    println!("websocket server starting at {}", "http://0.0.0.0:9001");
+    let connections_per_ip = ConnectionsPerIp::new(env_or("MAX_CONNECTIONS_PER_IP", DEFAULT_MAX_CONNECTIONS_PER_IP));
 
-    while let Ok((stream, _)) = listener.accept().await {
-        let Ok(websocket) = accept_async(stream).await else {
-            println!("failed to accept stream connection...");
-            continue
-        };
+    info!(?capacity, "admitting players");
 
-        let (mut websocket_writer, mut websocket_reader) = websocket.split();
+    let generator = Generator::from_env();
 
-        let (sender, mut receiver) = mpsc::unbounded_channel();
-        let player_id = PlayerId::random();
+    if let Generator::Stub(_) = generator {
+        warn!("using the stub image generator, monuments will never be completed");
+    }
 
-        let player_data = PlayerData {
-            id: player_id,
-            balance: 0,
-            position: Coordinate::default(),
-        };
+    let game = GameLoop::spawn(world, capacity, generator);
+    let gateway = Gateway::new(game.clone(), connections_per_ip);
+    let admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
 
-        world_clone.add(player_data).await;
-        manager.add(player_id, sender.clone()).await;
+    // Websockets are served on `/ws`, the dedicated port only exists for older clients
+    let legacy_listener = match std::env::var("LEGACY_WEBSOCKET_PORT") {
+        Ok(port) => Some(TcpListener::bind(("0.0.0.0", port.parse::<u16>()?)).await?),
+        Err(_) => None,
+    };
 
-        println!("player {:?} connected", player_id);
+    let api_listener = TcpListener::bind("0.0.0.0:3000").await?;
 
-        // Task: send to client
-        let write_task = tokio::spawn(async move {
-            while let Some(message) = receiver.recv().await {
-                if websocket_writer.send(message.try_into().unwrap()).await.is_err() {
-                    break;
-                }
-            }
-        });
+    let (stop_api_server, api_server_stopped) = oneshot::channel::<()>();
+    let api_server = tokio::spawn(build_server(api_listener, game.clone(), metrics, admin_token, gateway.clone(), async move {
+        let _ = api_server_stopped.await;
+    }));
 
-        // Task: receive from client
-        let scoped = ScopedManager::new(player_id, manager.clone());
-        let scoped_clone = scoped.clone();
-        let world_clone_2 = world_clone.clone();
+    let legacy_server = async {
+        match legacy_listener {
+            Some(listener) => websocket::accept(listener, gateway.clone()).await,
+            None => std::future::pending().await,
+        }
+    };
 
-        let read_task = tokio::spawn(async move {
-            while let Some(Ok(message)) = websocket_reader.next().await {
-                if let Ok(message) = SystemMessages::try_from(message) {
-                    println!("{:?} -> {:?}", player_id, message);
-                    handle_player_communication(scoped_clone.clone(), world_clone_2.clone(), message).await;
-                }
-            }
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        _ = legacy_server => {}
+    }
 
-            println!("player {:?} disconnected", player_id);
+    info!("shutting down, no longer accepting players");
 
-            scoped_clone.remove(player_id).await;
-            world_clone_2.remove(player_id).await;
-            scoped_clone.broadcast_except_self(SystemMessages::EnemyDisconnected { id: player_id }).await;
-        });
+    let players = game.shutdown(SystemMessages::ServerShuttingDown { reconnect_after: RECONNECT_AFTER }).await;
 
-        on_player_connect(scoped.clone(), world.clone()).await;
+    info!("notified {} players about the shutdown", players);
 
-        // Auto-cleanup when either task ends
-        tokio::spawn(async move {
-            let _ = tokio::join!(write_task, read_task);
-        });
+    // Writers close their sockets as soon as their queue is drained
+    if gateway.wait(CLOSE_FRAMES_TIMEOUT).await == false {
+        warn!("some connections did not close in time");
     }
 
-    Ok(())
-}
+    let pending = game.wait_for_pending_generations(SHUTDOWN_GRACE_PERIOD).await;
 
-async fn handle_player_communication(scope: ScopedManager, world: World, message: SystemMessages) {
-    match message {
-        SystemMessages::Ping => scope.broadcast_to_self(SystemMessages::Pong).await,
-        SystemMessages::Pong => {}
-        SystemMessages::Connected { .. } => {}
-        SystemMessages::Welcome { .. } => {}
-        SystemMessages::PlayerPosition { coordinate } => {
-            world.update_coordinate(scope.id, coordinate).await;
-            scope.broadcast_except_self(SystemMessages::EnemyPosition { id: scope.id, coordinate }).await
-        }
-        SystemMessages::MainPlayerSpawn { .. } => {}
-        SystemMessages::EnemyPlayerSpawn { .. } => {}
-        SystemMessages::BuildMonumentRequest { prompt } => {
-            if let Some(data) = world.get(scope.id).await {
-                match ComfyUI::new().generate(prompt.as_str()).await {
-                    Ok(id) => {
-                        let monument = Monument {
-                            id,
-                            description: prompt.into(),
-                            asset: "under-construction.png".into(),
-                            position: data.position.drift_by(3),
-                            under_construction: true,
-                        };
-
-                        let balance = world.decrement_balance_by(scope.id, 5).await;
-                        world.add_monument(monument.clone()).await;
-
-                        scope.broadcast_to_self(SystemMessages::MainPlayerCurrentBalance { balance }).await;
-                        scope.broadcast_to_all(SystemMessages::BuildMonument { monument }).await;
-                    }
-                    Err(_) => {
-                        // notify client that his generation failed...
-                    }
-                }
-            }
-        }
-        SystemMessages::MainPlayerPickedUpToken => {
-            let balance = world.increment_balance(scope.id).await;
-            scope.broadcast_to_self(SystemMessages::MainPlayerCurrentBalance { balance }).await;
-        }
-        _ => {}
+    if pending > 0 {
+        warn!("giving up on {} pending generations", pending);
     }
-}
 
-async fn on_player_connect(scoped: ScopedManager, world: World) {
-    if let Some(data) = world.get(scoped.id).await {
+    if let Err(error) = game.flush().await {
+        error!(?error, "failed to flush monuments")
+    }
 
-        // Spawn the main player
-        let player = scoped.broadcast_to_self(SystemMessages::MainPlayerSpawn { data: data.clone() });
+    let _ = stop_api_server.send(());
+    let _ = api_server.await;
 
-        // Then notify everyone that there is a new boss in town
-        let enemy = scoped.broadcast_except_self(SystemMessages::EnemyPlayerSpawn { data: data.clone() });
+    info!("server stopped");
 
-        for data in world.players().await {
-            if data.id != scoped.id {
-                scoped.broadcast_to_self(SystemMessages::EnemyPlayerSpawn { data }).await;
-            }
-        }
+    Ok(())
+}
 
-        for monument in world.monuments().await {
-            scoped.broadcast_to_self(SystemMessages::BuildMonument { monument }).await;
-        }
+/// Read a setting from the environment, falling back to the default when missing or invalid
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+/// Resolves once the process receives either SIGINT (ctrl+c) or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to listen for ctrl+c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-        tokio::join!(player, enemy);
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
 }
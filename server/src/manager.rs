@@ -1,77 +1,158 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
-use tokio::sync::Mutex;
+use metrics::{counter, gauge, histogram};
+use tokio::time::Instant;
+use tracing::warn;
 
 use shared::{PlayerId, SystemMessages};
 
-use crate::Sender;
+use crate::outbox::{SendError, Sender};
+use crate::telemetry::{BROADCAST_FAN_OUT, CONNECTED_PLAYERS, EVICTIONS};
 
-#[derive(Default, Clone)]
+/// Sent to players whose outbound queue overflowed right before they are disconnected
+const EVICTION_REASON: &str = "connection too slow to keep up with the world";
+
+struct Client {
+    sender: Sender,
+    last_seen: Instant,
+}
+
+impl Client {
+    /// Queue a message for this client, returns false once the client should be dropped
+    fn deliver(&self, id: &PlayerId, message: SystemMessages) -> bool {
+        match self.sender.send(message) {
+            Ok(()) => true,
+            Err(SendError::Full) => {
+                warn!(player = ?id, "player is too far behind, evicting");
+                counter!(EVICTIONS).increment(1);
+                self.sender.close_with(SystemMessages::Evicted { reason: EVICTION_REASON.into() });
+                false
+            }
+            Err(SendError::Closed) => false,
+        }
+    }
+}
+
+/// Outbound side of every connection, owned exclusively by the game loop.
+///
+/// Dropping a client drops its sender, its writer task then closes the socket
+/// and the connection reports back to the game loop once it's gone.
+#[derive(Default)]
 pub struct Manager {
-    inner: Arc<Mutex<HashMap<PlayerId, Sender>>>,
+    clients: HashMap<PlayerId, Client>,
+    /// Players dropped while delivering a message, the game loop takes them out of the world
+    dropped: Vec<PlayerId>,
 }
 
 impl Manager {
-    pub fn new() -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(HashMap::new())),
-        }
+    pub fn broadcast(&mut self, message: SystemMessages) {
+        histogram!(BROADCAST_FAN_OUT).record(self.clients.len() as f64);
+
+        self.deliver_where(|_| true, message);
     }
 
-    pub async fn broadcast(&self, message: SystemMessages) {
-        for sender in self.inner.lock().await.values() {
-            let _ = sender.send(message.clone());
-        }
+    /// Only deliver to the given players, e.g. those close enough to hear something
+    pub fn broadcast_among(&mut self, ids: &HashSet<PlayerId>, message: SystemMessages) {
+        histogram!(BROADCAST_FAN_OUT).record(ids.len() as f64);
+
+        self.deliver_where(|client_id| ids.contains(client_id), message);
     }
 
-    async fn broadcast_except(&self, id: PlayerId, message: SystemMessages) {
-        for (client_id, sender) in self.inner.lock().await.iter() {
-            if *client_id != id {
-                let _ = sender.send(message.clone());
+    pub fn broadcast_to(&mut self, id: PlayerId, message: SystemMessages) {
+        if let Some(client) = self.clients.get(&id) {
+            if client.deliver(&id, message) == false {
+                self.remove(id);
+                self.dropped.push(id);
             }
         }
     }
 
-    async fn broadcast_to(&self, id: PlayerId, message: SystemMessages) {
-        if let Some(sender) = self.inner.lock().await.get(&id) {
-            let _ = sender.send(message);
+    fn deliver_where(&mut self, recipient: impl Fn(&PlayerId) -> bool, message: SystemMessages) {
+        let dropped = &mut self.dropped;
+
+        self.clients.retain(|client_id, client| {
+            let kept = !recipient(client_id) || client.deliver(client_id, message.clone());
+
+            if !kept {
+                dropped.push(*client_id);
+            }
+
+            kept
+        });
+
+        self.record_connected_players();
+    }
+
+    /// Players dropped since the last call
+    pub fn take_dropped(&mut self) -> Vec<PlayerId> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Like [`Manager::broadcast_to`] but bypasses the queue capacity, used for the initial world sync
+    pub fn sync_to(&self, id: PlayerId, message: SystemMessages) {
+        if let Some(client) = self.clients.get(&id) {
+            let _ = client.sender.force(message);
         }
     }
 
-    pub async fn add(&self, id: PlayerId, sender: Sender) {
-        self.inner.lock().await.insert(id, sender);
+    pub fn add(&mut self, id: PlayerId, sender: Sender) {
+        self.clients.insert(id, Client { sender, last_seen: Instant::now() });
+        self.record_connected_players();
     }
 
-    async fn remove(&self, id: PlayerId) {
-        self.inner.lock().await.remove(&id);
+    pub fn contains(&self, id: PlayerId) -> bool {
+        self.clients.contains_key(&id)
     }
-}
 
-#[derive(Clone)]
-pub struct ScopedManager {
-    pub id: PlayerId,
-    inner: Manager,
-}
+    pub fn remove(&mut self, id: PlayerId) {
+        self.clients.remove(&id);
+        self.record_connected_players();
+    }
 
-impl ScopedManager {
-    pub fn new(id: PlayerId, parent: Manager) -> Self {
-        Self { id, inner: parent }
+    /// Disconnect the player right away, discarding anything still queued for it
+    pub fn kick(&mut self, id: PlayerId, reason: &str) -> bool {
+        let Some(client) = self.clients.remove(&id) else {
+            return false;
+        };
+
+        client.sender.close_with(SystemMessages::Evicted { reason: reason.into() });
+        self.record_connected_players();
+
+        true
     }
 
-    pub async fn broadcast_to_self(&self, message: SystemMessages) {
-        self.inner.broadcast_to(self.id, message).await
+    /// Record that the player has just shown signs of life
+    pub fn touch(&mut self, id: PlayerId) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.last_seen = Instant::now();
+        }
     }
 
-    pub async fn broadcast_except_self(&self, message: SystemMessages) {
-        self.inner.broadcast_except(self.id, message).await
+    /// Players that haven't sent anything (including pong frames) for longer than the timeout
+    pub fn unresponsive(&self, timeout: Duration) -> Vec<PlayerId> {
+        self.clients
+            .iter()
+            .filter(|(_, client)| client.last_seen.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect()
     }
 
-    pub async fn broadcast_to_all(&self, message: SystemMessages) {
-        self.inner.broadcast(message).await;
+    /// Deliver a final message to every player and drop their senders,
+    /// each writer task then drains its queue and closes the socket
+    pub fn shutdown(&mut self, message: SystemMessages) -> usize {
+        for client in self.clients.values() {
+            let _ = client.sender.force(message.clone());
+        }
+
+        let count = self.clients.len();
+        self.clients.clear();
+        self.record_connected_players();
+
+        count
     }
 
-    pub async fn remove(&self, id: PlayerId) {
-        self.inner.remove(id).await
+    fn record_connected_players(&self) {
+        gauge!(CONNECTED_PLAYERS).set(self.clients.len() as f64);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,98 @@
+use std::fmt::{Display, Formatter};
+
+use shared::PlayerId;
+
+pub const MIN_NAME_LENGTH: usize = 3;
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Punctuation allowed in names besides letters, digits and single spaces
+const ALLOWED_PUNCTUATION: &[char] = &['_', '-', '.'];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NameError {
+    TooShort,
+    TooLong,
+    InvalidCharacter(char),
+    Taken,
+}
+
+impl Display for NameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameError::TooShort => write!(f, "names need at least {} characters", MIN_NAME_LENGTH),
+            NameError::TooLong => write!(f, "names can't be longer than {} characters", MAX_NAME_LENGTH),
+            NameError::InvalidCharacter(character) => write!(f, "names can't contain '{}'", character),
+            NameError::Taken => write!(f, "this name is already taken"),
+        }
+    }
+}
+
+/// Trim the name and collapse repeated whitespace, then check it's acceptable as a display name
+pub fn normalize(name: &str) -> Result<String, NameError> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if let Some(character) = name
+        .chars()
+        .find(|character| !character.is_alphanumeric() && *character != ' ' && !ALLOWED_PUNCTUATION.contains(character))
+    {
+        return Err(NameError::InvalidCharacter(character));
+    }
+
+    match name.chars().count() {
+        length if length < MIN_NAME_LENGTH => Err(NameError::TooShort),
+        length if length > MAX_NAME_LENGTH => Err(NameError::TooLong),
+        _ => Ok(name),
+    }
+}
+
+/// Names are unique regardless of their case
+pub fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// How many distinct short default names there are, later attempts get longer ones
+pub const SHORT_DEFAULT_NAMES: u32 = 10_000;
+
+/// Name given to players until they pick one, distinct for every attempt
+pub fn default_name(id: PlayerId, attempt: u32) -> String {
+    let id: u32 = id.into();
+
+    if attempt < SHORT_DEFAULT_NAMES {
+        format!("Robot-{:04}", (id % SHORT_DEFAULT_NAMES + attempt) % SHORT_DEFAULT_NAMES)
+    } else {
+        format!("Robot-{:08x}", id.wrapping_add(attempt - SHORT_DEFAULT_NAMES))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn trims_and_collapses_whitespace() {
+        assert_eq!(normalize("  Big   Robot "), Ok("Big Robot".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert_eq!(normalize("ab"), Err(NameError::TooShort));
+        assert_eq!(normalize("a name way too long to fit"), Err(NameError::TooLong));
+        assert_eq!(normalize("<script>"), Err(NameError::InvalidCharacter('<')));
+    }
+
+    #[test]
+    fn default_names_never_repeat() {
+        let id = PlayerId::from(u32::MAX - 3);
+        let names: HashSet<_> = (0..SHORT_DEFAULT_NAMES * 2).map(|attempt| default_name(id, attempt)).collect();
+
+        assert_eq!(names.len(), SHORT_DEFAULT_NAMES as usize * 2);
+        assert!(names.iter().all(|name| normalize(name).as_ref() == Ok(name)));
+    }
+
+    #[test]
+    fn accepts_letters_from_any_language() {
+        assert_eq!(normalize("Zoë_Ünal"), Ok("Zoë_Ünal".to_string()));
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use shared::Coordinate;
+
+/// Tiles covered by monuments, so new ones can't be placed on top of them
+#[derive(Default)]
+pub struct Occupancy {
+    /// How many monuments cover each tile, monuments restored from before placement was
+    /// validated may overlap
+    tiles: HashMap<Coordinate, u32>,
+}
+
+impl Occupancy {
+    pub fn is_free(&self, center: Coordinate) -> bool {
+        center.footprint().all(|tile| self.tiles.contains_key(&tile) == false)
+    }
+
+    /// Whether a monument stands on the tile
+    pub fn is_covered(&self, tile: Coordinate) -> bool {
+        self.tiles.contains_key(&tile)
+    }
+
+    /// Take the footprint, `false` if any of its tiles is already covered
+    pub fn claim(&mut self, center: Coordinate) -> bool {
+        if self.is_free(center) == false {
+            return false;
+        }
+
+        self.occupy(center);
+        true
+    }
+
+    /// Take the footprint whether or not it overlaps another one
+    pub fn occupy(&mut self, center: Coordinate) {
+        for tile in center.footprint() {
+            *self.tiles.entry(tile).or_default() += 1;
+        }
+    }
+
+    pub fn release(&mut self, center: Coordinate) {
+        for tile in center.footprint() {
+            if let Some(count) = self.tiles.get_mut(&tile) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.tiles.remove(&tile);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::MONUMENT_FOOTPRINT;
+
+    use super::*;
+
+    #[test]
+    fn rejects_overlapping_footprints() {
+        let mut occupancy = Occupancy::default();
+        let center = Coordinate { x: 10, y: 10 };
+
+        assert!(occupancy.claim(center));
+        assert!(occupancy.claim(center) == false);
+        assert!(occupancy.claim(Coordinate { x: 10 + MONUMENT_FOOTPRINT * 2, y: 10 }) == false);
+        assert!(occupancy.claim(Coordinate { x: 10 + MONUMENT_FOOTPRINT * 2 + 1, y: 10 }));
+    }
+
+    #[test]
+    fn released_tiles_stay_covered_by_overlapping_monuments() {
+        let mut occupancy = Occupancy::default();
+        let center = Coordinate { x: 0, y: 0 };
+
+        occupancy.occupy(center);
+        occupancy.occupy(center);
+        occupancy.release(center);
+
+        assert!(occupancy.is_free(center) == false);
+
+        occupancy.release(center);
+
+        assert!(occupancy.is_free(center));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use shared::{PlayerId, SystemMessages};
+
+/// Bounded queue of messages waiting to be written to a single client.
+///
+/// Position updates of the same enemy supersede each other while still queued,
+/// so a slow client only ever holds the latest known position of every robot.
+pub fn channel(capacity: usize) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::default()),
+        notify: Notify::new(),
+        capacity,
+    });
+
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// The client fell too far behind
+    Full,
+    /// The queue was closed, the writer is going away
+    Closed,
+}
+
+enum Entry {
+    Message(SystemMessages),
+    /// Placeholder for the latest position of an enemy, kept aside so it can be replaced in place
+    Position(PlayerId),
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Entry>,
+    positions: HashMap<PlayerId, SystemMessages>,
+    closed: bool,
+}
+
+impl State {
+    /// Queue a message, superseding the pending position of the same enemy if any
+    fn push(&mut self, message: SystemMessages) {
+        if let SystemMessages::EnemyPosition { id, .. } = message {
+            if self.positions.insert(id, message).is_none() {
+                self.queue.push_back(Entry::Position(id));
+            }
+        } else {
+            if let Some(id) = lifecycle_of(&message) {
+                self.settle(id);
+            }
+
+            self.queue.push_back(Entry::Message(message));
+        }
+    }
+
+    /// Pin the pending position of the enemy to its slot, so later positions queue up behind
+    /// whatever comes next instead of jumping ahead of it
+    fn settle(&mut self, id: PlayerId) {
+        let Some(position) = self.positions.remove(&id) else {
+            return;
+        };
+
+        if let Some(entry) = self.queue.iter_mut().find(|entry| matches!(entry, Entry::Position(pending) if *pending == id)) {
+            *entry = Entry::Message(position);
+        }
+    }
+
+    fn pop(&mut self) -> Option<SystemMessages> {
+        match self.queue.pop_front()? {
+            Entry::Message(message) => Some(message),
+            Entry::Position(id) => self.positions.remove(&id),
+        }
+    }
+}
+
+/// Enemy whose robot the message spawns or despawns, its positions must not be reordered around it
+fn lifecycle_of(message: &SystemMessages) -> Option<PlayerId> {
+    match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(data.id),
+        SystemMessages::EnemyDisconnected { id } | SystemMessages::EnemyOutOfView { id } => Some(*id),
+        _ => None,
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+    capacity: usize,
+}
+
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    pub fn send(&self, message: SystemMessages) -> Result<(), SendError> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.closed {
+            return Err(SendError::Closed);
+        }
+
+        let supersedes = match &message {
+            SystemMessages::EnemyPosition { id, .. } => state.positions.contains_key(id),
+            _ => false,
+        };
+
+        if supersedes == false && state.queue.len() >= self.shared.capacity {
+            return Err(SendError::Full);
+        }
+
+        state.push(message);
+        drop(state);
+
+        self.shared.notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Queue a message regardless of the capacity, only meant for bursts whose size is
+    /// bounded by the world itself, such as syncing every monument to a new player
+    pub fn force(&self, message: SystemMessages) -> Result<(), SendError> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.closed {
+            return Err(SendError::Closed);
+        }
+
+        state.push(message);
+        drop(state);
+
+        self.shared.notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Discard everything still pending and close the queue with a final message
+    pub fn close_with(&self, message: SystemMessages) {
+        let mut state = self.shared.state.lock().unwrap();
+
+        state.queue.clear();
+        state.positions.clear();
+        state.push(message);
+        state.closed = true;
+        drop(state);
+
+        self.shared.notify.notify_one();
+    }
+
+    /// Close the queue, messages already queued are still delivered
+    pub fn close(&self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /// Next message to write, `None` once the queue is closed and drained
+    pub async fn recv(&mut self) -> Option<SystemMessages> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+
+                if let Some(message) = state.pop() {
+                    return Some(message);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{Coordinate, PlayerId, SystemMessages, ROBOT_SPEED};
+
+    use super::{channel, SendError};
+
+    fn position(id: PlayerId, x: i32) -> SystemMessages {
+        SystemMessages::EnemyPosition { id, coordinate: Coordinate { x, y: 0 }, timestamp: 0, speed: ROBOT_SPEED }
+    }
+
+    #[tokio::test]
+    async fn coalesces_positions_of_the_same_enemy() {
+        let (sender, mut receiver) = channel(8);
+        let enemy = PlayerId::random();
+
+        sender.send(position(enemy, 1)).unwrap();
+        sender.send(SystemMessages::Pong).unwrap();
+        sender.send(position(enemy, 2)).unwrap();
+
+        sender.close();
+
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::EnemyPosition { coordinate: Coordinate { x: 2, .. }, .. })));
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::Pong)));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn keeps_positions_on_their_side_of_spawns_and_despawns() {
+        let (sender, mut receiver) = channel(8);
+        let enemy = PlayerId::random();
+
+        sender.send(position(enemy, 1)).unwrap();
+        sender.send(SystemMessages::EnemyOutOfView { id: enemy }).unwrap();
+        sender.send(position(enemy, 2)).unwrap();
+        sender.send(SystemMessages::EnemyDisconnected { id: enemy }).unwrap();
+
+        sender.close();
+
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::EnemyPosition { coordinate: Coordinate { x: 1, .. }, .. })));
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::EnemyOutOfView { .. })));
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::EnemyPosition { coordinate: Coordinate { x: 2, .. }, .. })));
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::EnemyDisconnected { .. })));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_messages_once_full_and_closes_with_a_reason() {
+        let (sender, mut receiver) = channel(1);
+
+        sender.send(SystemMessages::Pong).unwrap();
+        assert_eq!(sender.send(SystemMessages::Pong), Err(SendError::Full));
+
+        sender.close_with(SystemMessages::Evicted { reason: "too slow".into() });
+        assert_eq!(sender.send(SystemMessages::Pong), Err(SendError::Closed));
+
+        assert!(matches!(receiver.recv().await, Some(SystemMessages::Evicted { .. })));
+        assert!(receiver.recv().await.is_none());
+    }
+}
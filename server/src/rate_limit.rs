@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use shared::SystemMessages;
+
+/// What happens to messages sent once the bucket of their kind ran dry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    /// Ignore the message, the player stays connected
+    Drop,
+    /// The client is misbehaving, close the connection
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    /// How many messages can be sent back to back
+    pub burst: u32,
+    /// How many messages per second are allowed over time
+    pub per_second: f64,
+    pub exceeded: Exceeded,
+}
+
+/// Limits of the messages players are expected to send
+const LIMITS: &[(&str, Limit)] = &[
+    ("PlayerPosition", Limit { burst: 20, per_second: 10.0, exceeded: Exceeded::Drop }),
+    ("BuildMonumentRequest", Limit { burst: 2, per_second: 1.0 / 30.0, exceeded: Exceeded::Drop }),
+    ("MainPlayerPickedUpToken", Limit { burst: 10, per_second: 5.0, exceeded: Exceeded::Drop }),
+    ("ChangeName", Limit { burst: 3, per_second: 0.1, exceeded: Exceeded::Drop }),
+    ("Emote", Limit { burst: 3, per_second: 0.5, exceeded: Exceeded::Drop }),
+    ("SendChat", Limit { burst: 5, per_second: 0.5, exceeded: Exceeded::Drop }),
+    ("ChangeCosmetics", Limit { burst: 5, per_second: 0.5, exceeded: Exceeded::Drop }),
+    ("Ping", Limit { burst: 5, per_second: 1.0, exceeded: Exceeded::Drop }),
+];
+
+/// Applied to every other kind, no legitimate client sends those in bulk
+const DEFAULT_LIMIT: Limit = Limit { burst: 10, per_second: 2.0, exceeded: Exceeded::Disconnect };
+
+pub fn limit_for(kind: &str) -> Limit {
+    LIMITS
+        .iter()
+        .find(|(limited, _)| *limited == kind)
+        .map(|(_, limit)| *limit)
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// The message is dropped, `notify` is only set for the first message dropped in a row
+    /// so a flooding client doesn't get a notice for every single message
+    Drop { notify: Option<Duration> },
+    Disconnect,
+}
+
+struct TokenBucket {
+    limit: Limit,
+    tokens: f64,
+    refilled_at: Instant,
+    /// Whether the player was already told about the current run of dropped messages
+    notified: bool,
+}
+
+impl TokenBucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self { limit, tokens: limit.burst as f64, refilled_at: now, notified: false }
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Time until the next message of this kind will be accepted
+    fn retry_after(&self) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens) / self.limit.per_second)
+    }
+}
+
+/// Token buckets of a single connection, one per message kind
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<&'static str, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn check(&mut self, message: &SystemMessages, now: Instant) -> Verdict {
+        let kind = message.kind();
+
+        let bucket = self.buckets
+            .entry(kind)
+            .or_insert_with(|| TokenBucket::new(limit_for(kind), now));
+
+        if bucket.take(now) {
+            bucket.notified = false;
+            return Verdict::Allow;
+        }
+
+        match bucket.limit.exceeded {
+            Exceeded::Disconnect => Verdict::Disconnect,
+            Exceeded::Drop if bucket.notified => Verdict::Drop { notify: None },
+            Exceeded::Drop => {
+                bucket.notified = true;
+                Verdict::Drop { notify: Some(bucket.retry_after()) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::Coordinate;
+
+    use super::*;
+
+    fn position() -> SystemMessages {
+        SystemMessages::PlayerPosition { coordinate: Coordinate::default(), sequence: 0 }
+    }
+
+    #[test]
+    fn drops_messages_past_the_burst_and_notifies_once() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let burst = limit_for("PlayerPosition").burst;
+
+        for _ in 0..burst {
+            assert_eq!(limiter.check(&position(), now), Verdict::Allow);
+        }
+
+        assert!(matches!(limiter.check(&position(), now), Verdict::Drop { notify: Some(_) }));
+        assert_eq!(limiter.check(&position(), now), Verdict::Drop { notify: None });
+
+        // Other kinds have their own bucket
+        assert_eq!(limiter.check(&SystemMessages::Ping, now), Verdict::Allow);
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let limit = limit_for("PlayerPosition");
+
+        for _ in 0..=limit.burst {
+            limiter.check(&position(), now);
+        }
+
+        let later = now + Duration::from_secs_f64(1.0 / limit.per_second);
+
+        assert_eq!(limiter.check(&position(), later), Verdict::Allow);
+    }
+
+    #[test]
+    fn disconnects_clients_flooding_unexpected_messages() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let message = SystemMessages::Pong;
+
+        for _ in 0..DEFAULT_LIMIT.burst {
+            assert_eq!(limiter.check(&message, now), Verdict::Allow);
+        }
+
+        assert_eq!(limiter.check(&message, now), Verdict::Disconnect);
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use metrics::{describe_counter, describe_gauge, describe_histogram, gauge, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing_subscriber::EnvFilter;
+
+pub const CONNECTED_PLAYERS: &str = "imaginarium_connected_players";
+pub const WAITING_PLAYERS: &str = "imaginarium_waiting_players";
+pub const REJECTED_CONNECTIONS: &str = "imaginarium_rejected_connections_total";
+pub const MESSAGES_RECEIVED: &str = "imaginarium_messages_received_total";
+pub const BROADCAST_FAN_OUT: &str = "imaginarium_broadcast_fan_out";
+pub const EVICTIONS: &str = "imaginarium_evictions_total";
+pub const RATE_LIMITED: &str = "imaginarium_rate_limited_messages_total";
+pub const GENERATION_QUEUE_DEPTH: &str = "imaginarium_generation_queue_depth";
+pub const GENERATION_DURATION: &str = "imaginarium_generation_duration_seconds";
+pub const GENERATION_FAILURES: &str = "imaginarium_generation_failures_total";
+pub const COMFYUI_REQUEST_DURATION: &str = "imaginarium_comfyui_request_duration_seconds";
+pub const TICK_DURATION: &str = "imaginarium_tick_duration_seconds";
+pub const WEBHOOK_FAILURES: &str = "imaginarium_webhook_failures_total";
+pub const RESIDENT_MEMORY: &str = "process_resident_memory_bytes";
+
+/// Log levels are configured through `RUST_LOG`, e.g. `RUST_LOG=server=debug`
+pub fn init_tracing() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+}
+
+/// Install the global metrics recorder, the returned handle renders the `/metrics` route
+pub fn install_recorder() -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(TICK_DURATION.into()),
+            &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25],
+        )
+        .and_then(|builder| builder.set_buckets_for_metric(
+            Matcher::Full(BROADCAST_FAN_OUT.into()),
+            &[1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0],
+        ))
+        .and_then(|builder| builder.set_buckets_for_metric(
+            Matcher::Full(GENERATION_DURATION.into()),
+            &[5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0],
+        ))
+        .and_then(|builder| builder.set_buckets_for_metric(
+            Matcher::Full(COMFYUI_REQUEST_DURATION.into()),
+            &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0],
+        ))
+        .expect("invalid histogram buckets")
+        .install_recorder()
+        .expect("failed to install the metrics recorder");
+
+    describe();
+
+    // Histograms are only drained when rendering unless upkeep runs in the background
+    let upkeep = handle.clone();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+
+            if let Some(bytes) = resident_memory() {
+                gauge!(RESIDENT_MEMORY).set(bytes as f64);
+            }
+        }
+    });
+
+    handle
+}
+
+fn describe() {
+    describe_gauge!(CONNECTED_PLAYERS, "Players currently connected");
+    describe_gauge!(WAITING_PLAYERS, "Players waiting for a free slot in the world");
+    describe_counter!(REJECTED_CONNECTIONS, "Connections refused for exceeding the per address limit");
+    describe_counter!(MESSAGES_RECEIVED, "Messages received from players, by kind");
+    describe_histogram!(BROADCAST_FAN_OUT, "Recipients of each broadcast");
+    describe_counter!(EVICTIONS, "Players disconnected for falling too far behind");
+    describe_counter!(RATE_LIMITED, "Messages rejected for exceeding the rate limit, by kind");
+    describe_gauge!(GENERATION_QUEUE_DEPTH, "Monuments waiting for their generation to complete");
+    describe_histogram!(GENERATION_DURATION, Unit::Seconds, "Time between queueing a generation and its webhook");
+    describe_counter!(GENERATION_FAILURES, "Generation requests rejected by ComfyUI");
+    describe_histogram!(COMFYUI_REQUEST_DURATION, Unit::Seconds, "Latency of queueing a prompt on ComfyUI");
+    describe_histogram!(TICK_DURATION, Unit::Seconds, "Time spent processing a single game loop tick");
+    describe_counter!(WEBHOOK_FAILURES, "Generation webhooks that could not be processed");
+    describe_gauge!(RESIDENT_MEMORY, Unit::Bytes, "Resident memory of the server process");
+}
+
+/// Only available on linux, read from `/proc` so no extra dependency is needed
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    Some(kilobytes * 1024)
+}
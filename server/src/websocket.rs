@@ -0,0 +1,167 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use hyper_util::rt::TokioIo;
+use metrics::counter;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+use crate::admission::{ConnectionsPerIp, Permit};
+use crate::connection;
+use crate::game_loop::GameLoop;
+use crate::telemetry::REJECTED_CONNECTIONS;
+
+/// Admits websocket connections into the game, shared by the `/ws` route and the legacy port
+#[derive(Clone)]
+pub struct Gateway {
+    game: GameLoop,
+    connections_per_ip: ConnectionsPerIp,
+    connections: TaskTracker,
+}
+
+impl Gateway {
+    pub fn new(game: GameLoop, connections_per_ip: ConnectionsPerIp) -> Self {
+        Self { game, connections_per_ip, connections: TaskTracker::new() }
+    }
+
+    /// Wait for every connection to be closed or the timeout to expire, returns false on timeout
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        self.connections.close();
+        tokio::time::timeout(timeout, self.connections.wait()).await.is_ok()
+    }
+
+    /// Refused before the handshake so a single address can't exhaust the server
+    fn admit(&self, address: IpAddr) -> Option<Permit> {
+        let permit = self.connections_per_ip.acquire(address);
+
+        if permit.is_none() {
+            warn!(%address, "too many connections from the same address");
+            counter!(REJECTED_CONNECTIONS).increment(1);
+        }
+
+        permit
+    }
+}
+
+pub fn router<S>(gateway: Gateway) -> Router<S> {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(gateway)
+}
+
+/// Complete the websocket handshake by hand so the upgraded connection can be served
+/// by the exact same code as the sockets accepted on the legacy port
+async fn upgrade(
+    State(gateway): State<Gateway>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+) -> Response {
+    let headers = request.headers();
+
+    let is_websocket = header_is(headers, header::UPGRADE, "websocket")
+        && header_is(headers, header::SEC_WEBSOCKET_VERSION, "13");
+
+    let Some(key) = headers.get(header::SEC_WEBSOCKET_KEY).filter(|_| is_websocket) else {
+        return (StatusCode::BAD_REQUEST, "expected a websocket upgrade").into_response();
+    };
+
+    let Some(permit) = gateway.admit(client_address(peer, headers)) else {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    };
+
+    let accept = derive_accept_key(key.as_bytes());
+    let game = gateway.game.clone();
+
+    gateway.connections.spawn(async move {
+        let _permit = permit;
+
+        match hyper::upgrade::on(request).await {
+            Ok(upgraded) => {
+                let websocket = WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+                connection::handle(websocket, game).await;
+            }
+            Err(error) => warn!(?error, "failed to upgrade connection"),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Serve raw websocket connections on a dedicated port, kept for clients built before `/ws` existed
+pub async fn accept(listener: TcpListener, gateway: Gateway) {
+    if let Ok(address) = listener.local_addr() {
+        info!("legacy websocket server starting at http://{}", address);
+    }
+
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                error!(?error, "failed to accept connection");
+                return;
+            }
+        };
+
+        let Some(permit) = gateway.admit(address.ip()) else {
+            continue;
+        };
+
+        let game = gateway.game.clone();
+
+        gateway.connections.spawn(async move {
+            let _permit = permit;
+
+            let Ok(websocket) = accept_async(stream).await else {
+                warn!("failed to accept stream connection...");
+                return;
+            };
+
+            connection::handle(websocket, game).await;
+        });
+    }
+}
+
+fn header_is(headers: &HeaderMap, name: header::HeaderName, expected: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value: &HeaderValue| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case(expected))
+}
+
+/// Behind the reverse proxy every connection comes from the proxy itself,
+/// the actual client is the last address it appended to `X-Forwarded-For`
+fn client_address(peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = peer.ip();
+
+    let behind_proxy = match peer {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local(),
+    };
+
+    if behind_proxy == false {
+        return peer;
+    }
+
+    headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(peer)
+}
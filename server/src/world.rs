@@ -1,64 +1,196 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
 
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
 
-use shared::{Coordinate, Monument, PlayerData, PlayerId};
+use shared::pathfinding::find_path;
+use shared::{ChunkId, Coordinate, Cosmetics, Monument, PlayerData, PlayerId};
 
-#[derive(Default, Clone)]
+use crate::names::{self, NameError};
+use crate::occupancy::Occupancy;
+
+const MONUMENTS_CACHE: &str = "./assets/monuments.jsonl";
+
+/// Authoritative state of the world, owned exclusively by the game loop
+#[derive(Default)]
 pub struct World {
-    inner: Arc<Mutex<HashMap<PlayerId, PlayerData>>>,
-    monuments: Arc<Mutex<HashMap<u32, Monument>>>,
+    players: HashMap<PlayerId, PlayerData>,
+    monuments: HashMap<u32, Monument>,
+    /// Owner of every name in use, keyed by [`names::key`]
+    names: HashMap<String, PlayerId>,
+    /// Footprints of every visible monument, and of placements still waiting on the generator
+    occupancy: Occupancy,
+    /// Ids of the monuments standing in every chunk
+    chunks: HashMap<ChunkId, HashSet<u32>>,
 }
 
 impl World {
-    pub async fn get(&self, id: PlayerId) -> Option<PlayerData> {
-        self.inner.lock().await.get(&id).cloned()
+    pub fn get(&self, id: PlayerId) -> Option<&PlayerData> {
+        self.players.get(&id)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item=&PlayerData> {
+        self.players.values()
     }
 
-    pub async fn players(&self) -> Vec<PlayerData> {
-        self.inner.lock().await.values().cloned().collect()
+    pub fn player_count(&self) -> usize {
+        self.players.len()
     }
 
-    pub async fn monuments(&self) -> Vec<Monument> {
-        self.monuments.lock().await.values().cloned().collect()
+    /// Players within `radius` tiles of the coordinate
+    pub fn players_near(&self, coordinate: Coordinate, radius: f32) -> impl Iterator<Item=&PlayerData> {
+        self.players.values().filter(move |data| data.position.distance(&coordinate) <= radius)
     }
 
-    pub async fn add_monument(&self, monument: Monument) {
-        self.monuments.lock().await.insert(monument.id, monument);
+    pub fn monuments(&self) -> impl Iterator<Item=&Monument> {
+        self.monuments.values()
     }
 
-    pub async fn complete_monument(&self, id: u32, asset: &str) {
-        let mut monuments = self.monuments.lock().await;
+    pub fn monuments_in(&self, chunk: ChunkId) -> impl Iterator<Item=&Monument> {
+        self.chunks
+            .get(&chunk)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.monuments.get(id))
+    }
+
+    pub fn add_monument(&mut self, monument: Monument) {
+        self.chunks.entry(monument.position.chunk()).or_default().insert(monument.id);
+
+        if let Some(previous) = self.monuments.insert(monument.id, monument) {
+            self.forget_monument(&previous);
+        }
+    }
+
+    /// Mark the monument as built, returns it so it can be persisted
+    pub fn complete_monument(&mut self, id: u32, asset: &str) -> Option<&Monument> {
+        let monument = self.monuments.get_mut(&id)?;
+
+        monument.asset = asset.to_string();
+        monument.under_construction = false;
+
+        Some(monument)
+    }
 
-        if let Some(monument) = monuments.get_mut(&id) {
-            monument.asset = asset.to_string();
-            monument.under_construction = false;
+    pub fn remove_monument(&mut self, id: u32) -> Option<Monument> {
+        let monument = self.monuments.remove(&id)?;
+        self.forget_monument(&monument);
 
-            if let Err(error) = self.cache_monument(&monument).await {
-                println!("failed to store monument: {:?}", error)
+        Some(monument)
+    }
+
+    /// Clear the indexes of a monument that's no longer in the world
+    fn forget_monument(&mut self, monument: &Monument) {
+        if !monument.hidden {
+            self.occupancy.release(monument.position);
+        }
+
+        let chunk = monument.position.chunk();
+
+        if let Some(ids) = self.chunks.get_mut(&chunk) {
+            if self.monuments.get(&monument.id).is_none_or(|current| current.position.chunk() != chunk) {
+                ids.remove(&monument.id);
+            }
+
+            if ids.is_empty() {
+                self.chunks.remove(&chunk);
             }
         }
     }
 
-    pub async fn add(&self, data: PlayerData) {
-        self.inner.lock().await.insert(data.id, data);
+    /// Claim the ground for a monument ahead of its generation, `false` if it overlaps another one
+    pub fn reserve_placement(&mut self, coordinate: Coordinate) -> bool {
+        self.occupancy.claim(coordinate)
+    }
+
+    /// Whether a robot can walk between both coordinates without going through a monument
+    pub fn is_reachable(&self, from: Coordinate, to: Coordinate) -> bool {
+        find_path(from, to, |tile| self.occupancy.is_covered(tile)).is_some()
     }
 
-    pub async fn remove(&self, id: PlayerId) {
-        self.inner.lock().await.remove(&id);
+    /// Give the ground back when the monument never made it
+    pub fn release_placement(&mut self, coordinate: Coordinate) {
+        self.occupancy.release(coordinate);
     }
 
-    pub async fn update_coordinate(&self, id: PlayerId, coordinate: Coordinate) {
-        if let Some(data) = self.inner.lock().await.get_mut(&id) {
+    /// Hidden monuments give their ground back, players can't see them so they shouldn't bump into them.
+    /// Shown again, they take it back even if something was built there in the meantime
+    pub fn set_monument_hidden(&mut self, id: u32, hidden: bool) -> Option<&Monument> {
+        let monument = self.monuments.get_mut(&id)?;
+
+        if monument.hidden != hidden {
+            if hidden {
+                self.occupancy.release(monument.position);
+            } else {
+                self.occupancy.occupy(monument.position);
+            }
+        }
+
+        monument.hidden = hidden;
+
+        Some(monument)
+    }
+
+    /// Number of monuments still waiting for their generation webhook
+    pub fn pending_generations(&self) -> usize {
+        self.monuments.values().filter(|monument| monument.under_construction).count()
+    }
+
+    pub fn add(&mut self, data: PlayerData) {
+        self.names.insert(names::key(&data.name), data.id);
+        self.players.insert(data.id, data);
+    }
+
+    pub fn remove(&mut self, id: PlayerId) -> Option<PlayerData> {
+        let data = self.players.remove(&id)?;
+        self.names.remove(&names::key(&data.name));
+
+        Some(data)
+    }
+
+    pub fn is_name_available(&self, name: &str) -> bool {
+        self.names.contains_key(&names::key(name)) == false
+    }
+
+    /// A default name no one else is using. Every player holds a single name, so once the short
+    /// names run out one of the next few longer ones is bound to be free
+    pub fn available_default_name(&self, id: PlayerId) -> String {
+        let attempts = names::SHORT_DEFAULT_NAMES.saturating_add(self.players.len() as u32 + 1);
+
+        (0..attempts)
+            .map(|attempt| names::default_name(id, attempt))
+            .find(|name| self.is_name_available(name))
+            .expect("more default names than players")
+    }
+
+    /// Give the player a new name, the name must already be normalized
+    pub fn rename(&mut self, id: PlayerId, name: String) -> Result<(), NameError> {
+        let Some(data) = self.players.get_mut(&id) else {
+            return Ok(());
+        };
+
+        match self.names.get(&names::key(&name)) {
+            Some(owner) if *owner != id => return Err(NameError::Taken),
+            _ => {}
+        }
+
+        self.names.remove(&names::key(&data.name));
+        self.names.insert(names::key(&name), id);
+        data.name = name;
+
+        Ok(())
+    }
+
+    pub fn update_coordinate(&mut self, id: PlayerId, coordinate: Coordinate) {
+        if let Some(data) = self.players.get_mut(&id) {
             data.position = coordinate
         }
     }
 
-    pub async fn increment_balance(&self, id: PlayerId) -> u32 {
-        if let Some(data) = self.inner.lock().await.get_mut(&id) {
+    pub fn increment_balance(&mut self, id: PlayerId) -> u32 {
+        if let Some(data) = self.players.get_mut(&id) {
             data.balance += 1;
             data.balance
         } else {
@@ -66,41 +198,146 @@ impl World {
         }
     }
 
-    pub async fn decrement_balance_by(&self, id: PlayerId, amount: u32) -> u32 {
-        if let Some(data) = self.inner.lock().await.get_mut(&id) {
-            data.balance -= amount;
-            data.balance
-        } else {
-            0
-        }
+    /// Take tokens from the player's balance, `None` if they can't afford it
+    pub fn spend(&mut self, id: PlayerId, amount: u32) -> Option<u32> {
+        let data = self.players.get_mut(&id)?;
+        data.balance = data.balance.checked_sub(amount)?;
+
+        Some(data.balance)
     }
 
-    async fn cache_monument(&self, monument: &Monument) -> std::io::Result<()> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("./assets/monuments.jsonl")
-            .await?;
+    /// Swap the player's loadout, paying for the parts that change. Returns the new balance
+    pub fn change_cosmetics(&mut self, id: PlayerId, cosmetics: Cosmetics) -> Option<u32> {
+        let price = self.players.get(&id)?.cosmetics.price_of(&cosmetics);
+        let balance = self.spend(id, price)?;
 
-        let line = serde_json::to_string(monument)?;
-        file.write_all(line.as_bytes()).await?;
-        file.write_all(b"\n").await?;
+        if let Some(data) = self.players.get_mut(&id) {
+            data.cosmetics = cosmetics;
+        }
 
-        Ok(())
+        Some(balance)
+    }
+
+    /// Add (or subtract) tokens from the player's balance, never going below zero
+    pub fn adjust_balance(&mut self, id: PlayerId, amount: i64) -> Option<u32> {
+        let data = self.players.get_mut(&id)?;
+        data.balance = (data.balance as i64 + amount).clamp(0, u32::MAX as i64) as u32;
+
+        Some(data.balance)
     }
 
-    pub async fn restore_monuments_from_cache(&self) -> tokio::io::Result<()> {
-        let file = File::open("./assets/monuments.jsonl").await?;
+    pub async fn restore_monuments_from_cache(&mut self) -> tokio::io::Result<()> {
+        let file = File::open(MONUMENTS_CACHE).await?;
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
 
-        let mut monuments = self.monuments.lock().await;
-
         while let Some(line) = lines.next_line().await? {
             let monument: Monument = serde_json::from_str(&line)?;
-            monuments.insert(monument.id, monument);
+
+            if !monument.hidden {
+                self.occupancy.occupy(monument.position);
+            }
+
+            self.add_monument(monument);
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Writes to the monument cache. Appends and rewrites all go through a single task, in the order
+/// they were requested, so they never interleave on disk
+pub struct MonumentCache {
+    writes: mpsc::UnboundedSender<CacheWrite>,
+}
+
+enum CacheWrite {
+    Append(Monument),
+    Rewrite {
+        monuments: Vec<Monument>,
+        done: Option<oneshot::Sender<std::io::Result<()>>>,
+    },
+}
+
+impl MonumentCache {
+    pub fn spawn() -> Self {
+        let (writes, mut receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Some(write) = receiver.recv().await {
+                match write {
+                    CacheWrite::Append(monument) => {
+                        if let Err(error) = append_monument(&monument).await {
+                            error!(?error, "failed to store monument")
+                        }
+                    }
+                    CacheWrite::Rewrite { monuments, done: Some(done) } => {
+                        let _ = done.send(rewrite_monuments(monuments).await);
+                    }
+                    CacheWrite::Rewrite { monuments, done: None } => {
+                        if let Err(error) = rewrite_monuments(monuments).await {
+                            error!(?error, "failed to store monuments")
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { writes }
+    }
+
+    /// Add a freshly completed monument to the end of the cache
+    pub fn append(&self, monument: Monument) {
+        let _ = self.writes.send(CacheWrite::Append(monument));
+    }
+
+    /// The cache is append only, rewrite it whenever a monument changes after being completed
+    pub fn rewrite(&self, monuments: Vec<Monument>) {
+        let _ = self.writes.send(CacheWrite::Rewrite { monuments, done: None });
+    }
+
+    /// Rewrite the cache once every write requested before is done. The rewrite is queued right
+    /// away, the returned future only waits for it
+    pub fn flush(&self, monuments: Vec<Monument>) -> impl Future<Output = std::io::Result<()>> + use<> {
+        let (done, result) = oneshot::channel();
+        let _ = self.writes.send(CacheWrite::Rewrite { monuments, done: Some(done) });
+
+        async move {
+            result.await.unwrap_or_else(|_| Err(std::io::Error::other("monument cache writer is gone")))
+        }
+    }
+}
+
+async fn append_monument(monument: &Monument) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(MONUMENTS_CACHE)
+        .await?;
+
+    let line = serde_json::to_string(monument)?;
+    file.write_all(line.as_bytes()).await?;
+    file.write_all(b"\n").await?;
+
+    Ok(())
+}
+
+/// Write every completed monument to a temporary file, then rename it over the cache. Renaming is
+/// atomic, so a crash mid-write leaves the previous cache intact. Only the cache task calls this,
+/// which is what keeps the temporary file from being written by two rewrites at once
+async fn rewrite_monuments(monuments: Vec<Monument>) -> std::io::Result<()> {
+    let temporary = format!("{}.tmp", MONUMENTS_CACHE);
+
+    let mut file = File::create(&temporary).await?;
+
+    for monument in monuments.iter().filter(|monument| !monument.under_construction) {
+        let line = serde_json::to_string(monument)?;
+        file.write_all(line.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+    }
+
+    file.sync_all().await?;
+    tokio::fs::rename(&temporary, MONUMENTS_CACHE).await?;
+
+    Ok(())
+}
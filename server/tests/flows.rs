@@ -0,0 +1,525 @@
+mod harness;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use shared::{Accessory, ChatChannel, Coordinate, CHUNK_SIZE, Cosmetics, EmoteKind, MONUMENT_FOOTPRINT, MONUMENT_PRICE, ROBOT_SPEED, RobotColor, SystemMessages};
+
+use harness::TestServer;
+
+#[tokio::test]
+async fn players_are_spawned_and_told_about_each_other() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect_legacy().await;
+
+    let spawned = alice.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(data.id),
+        _ => None,
+    }).await;
+
+    assert_eq!(spawned, bob.id);
+
+    let synced = bob.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(data.id),
+        _ => None,
+    }).await;
+
+    assert_eq!(synced, alice.id);
+}
+
+#[tokio::test]
+async fn movements_are_fanned_out_to_other_players() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+
+    let destination = Coordinate { x: 4, y: -2 };
+
+    let sent_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: destination, sequence: 1 }).await;
+
+    for enemy in [&mut bob, &mut carol] {
+        let (id, coordinate, timestamp, speed) = enemy.expect(|message| match message {
+            SystemMessages::EnemyPosition { id, coordinate, timestamp, speed } => Some((id, coordinate, timestamp, speed)),
+            _ => None,
+        }).await;
+
+        assert_eq!(id, alice.id);
+        assert_eq!(coordinate, destination);
+        assert!(timestamp >= sent_at);
+        assert_eq!(speed, ROBOT_SPEED);
+    }
+}
+
+#[tokio::test]
+async fn picking_up_tokens_increases_the_balance() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+
+    for expected in 1..=3 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+
+        let balance = alice.expect(|message| match message {
+            SystemMessages::MainPlayerCurrentBalance { balance } => Some(balance),
+            _ => None,
+        }).await;
+
+        assert_eq!(balance, expected);
+    }
+}
+
+#[tokio::test]
+async fn building_a_monument_spends_tokens_and_is_shown_to_everyone() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    for _ in 0..5 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.expect(|message| match message {
+        SystemMessages::MainPlayerCurrentBalance { balance: 5 } => Some(()),
+        _ => None,
+    }).await;
+
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a giraffe".into(), coordinate: Coordinate { x: 3, y: 3 } }).await;
+
+    let balance = alice.expect(|message| match message {
+        SystemMessages::MainPlayerCurrentBalance { balance } => Some(balance),
+        _ => None,
+    }).await;
+
+    assert_eq!(balance, 0);
+
+    let alice_id = alice.id;
+
+    for player in [&mut alice, &mut bob] {
+        let monument = player.expect(|message| match message {
+            SystemMessages::BuildMonument { monument } => Some(monument),
+            _ => None,
+        }).await;
+
+        assert_eq!(monument.description, "a giraffe");
+        assert!(monument.under_construction);
+        assert_eq!(monument.owner, Some(alice_id));
+        assert!(monument.owner_name.starts_with("Robot-"));
+        assert!(monument.created_at > 0);
+    }
+}
+
+#[tokio::test]
+async fn back_to_back_monuments_are_only_paid_for_once() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+
+    for _ in 0..MONUMENT_PRICE {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.expect(|message| match message {
+        SystemMessages::MainPlayerCurrentBalance { balance } if balance == MONUMENT_PRICE => Some(()),
+        _ => None,
+    }).await;
+
+    // Far enough apart that only the balance can get in the way of the second one
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a giraffe".into(), coordinate: Coordinate { x: 3, y: 3 } }).await;
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a zebra".into(), coordinate: Coordinate { x: -3, y: -3 } }).await;
+
+    // Both requests may not land on the same tick, so the first can be queued before the second is rejected
+    let mut outcomes = Vec::new();
+
+    for _ in 0..2 {
+        outcomes.push(alice.expect(|message| match message {
+            SystemMessages::BuildMonument { monument } => Some(Ok(monument.description)),
+            SystemMessages::BuildMonumentRejected { reason } => Some(Err(reason)),
+            _ => None,
+        }).await);
+    }
+
+    outcomes.sort();
+
+    assert_eq!(outcomes, [Ok("a giraffe".to_string()), Err("not enough tokens".to_string())]);
+}
+
+#[tokio::test]
+async fn completed_generations_are_broadcast() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    for _ in 0..5 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a lighthouse".into(), coordinate: Coordinate { x: -3, y: 3 } }).await;
+
+    let id = bob.expect(|message| match message {
+        SystemMessages::BuildMonument { monument } => Some(monument.id),
+        _ => None,
+    }).await;
+
+    assert!(server.complete_generation(id).await.is_success());
+
+    for player in [&mut alice, &mut bob] {
+        let (completed, asset) = player.expect(|message| match message {
+            SystemMessages::MonumentCompleted { id, asset } => Some((id, asset)),
+            _ => None,
+        }).await;
+
+        assert_eq!(completed, id);
+        assert!(asset.ends_with(".png"));
+    }
+}
+
+#[tokio::test]
+async fn disconnects_are_broadcast() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let bob = server.connect().await;
+    let bob_id = bob.id;
+
+    bob.close().await;
+
+    let disconnected = alice.expect(|message| match message {
+        SystemMessages::EnemyDisconnected { id } => Some(id),
+        _ => None,
+    }).await;
+
+    assert_eq!(disconnected, bob_id);
+}
+
+#[tokio::test]
+async fn names_are_validated_unique_and_broadcast() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send(SystemMessages::ChangeName { name: "  Alice ".into() }).await;
+
+    let (id, name) = bob.expect(|message| match message {
+        SystemMessages::PlayerRenamed { id, name } => Some((id, name)),
+        _ => None,
+    }).await;
+
+    assert_eq!(id, alice.id);
+    assert_eq!(name, "Alice");
+
+    bob.send(SystemMessages::ChangeName { name: "ALICE".into() }).await;
+
+    bob.expect(|message| match message {
+        SystemMessages::NameRejected { .. } => Some(()),
+        _ => None,
+    }).await;
+
+    let mut carol = server.connect().await;
+
+    let synced = carol.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } if data.id == alice.id => Some(data.name),
+        _ => None,
+    }).await;
+
+    assert_eq!(synced, "Alice");
+}
+
+#[tokio::test]
+async fn cosmetics_are_paid_for_and_broadcast() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    let cosmetics = Cosmetics { color: RobotColor::Red, accessory: Accessory::TopHat };
+
+    alice.send(SystemMessages::ChangeCosmetics { cosmetics }).await;
+
+    alice.expect(|message| match message {
+        SystemMessages::CosmeticsRejected { .. } => Some(()),
+        _ => None,
+    }).await;
+
+    let price = Cosmetics::default().price_of(&cosmetics);
+
+    for _ in 0..price + 1 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.send(SystemMessages::ChangeCosmetics { cosmetics }).await;
+
+    let (id, changed) = bob.expect(|message| match message {
+        SystemMessages::PlayerCosmeticsChanged { id, cosmetics } => Some((id, cosmetics)),
+        _ => None,
+    }).await;
+
+    assert_eq!(id, alice.id);
+    assert_eq!(changed, cosmetics);
+
+    alice.expect(|message| match message {
+        SystemMessages::MainPlayerCurrentBalance { balance: 1 } => Some(()),
+        _ => None,
+    }).await;
+}
+
+#[tokio::test]
+async fn proximity_chat_is_only_heard_nearby() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+
+    bob.send(SystemMessages::PlayerPosition { coordinate: Coordinate { x: CHUNK_SIZE * 2, y: CHUNK_SIZE * 2 }, sequence: 1 }).await;
+
+    alice.expect(|message| match message {
+        SystemMessages::EnemyOutOfView { id } if id == bob.id => Some(()),
+        _ => None,
+    }).await;
+
+    alice.send(SystemMessages::SendChat { channel: ChatChannel::Proximity, text: "psst, carol".into() }).await;
+    alice.send(SystemMessages::SendChat { channel: ChatChannel::Global, text: "hello  everyone".into() }).await;
+
+    let heard = |message: SystemMessages| match message {
+        SystemMessages::Chat { id, channel, text, .. } => Some((id, channel, text)),
+        _ => None,
+    };
+
+    assert_eq!(carol.expect(heard).await, (alice.id, ChatChannel::Proximity, "psst, carol".to_string()));
+    assert_eq!(carol.expect(heard).await, (alice.id, ChatChannel::Global, "hello everyone".to_string()));
+    assert_eq!(bob.expect(heard).await, (alice.id, ChatChannel::Global, "hello everyone".to_string()));
+}
+
+#[tokio::test]
+async fn emotes_are_shown_to_other_players() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send(SystemMessages::Emote { kind: EmoteKind::Wave }).await;
+
+    let emote = bob.expect(|message| match message {
+        SystemMessages::EnemyEmote { id, kind } => Some((id, kind)),
+        _ => None,
+    }).await;
+
+    assert_eq!(emote, (alice.id, EmoteKind::Wave));
+}
+
+#[tokio::test]
+async fn looks_and_emotes_only_reach_players_nearby() {
+    let server = TestServer::start().await;
+
+    let far_away = Coordinate { x: CHUNK_SIZE * 2, y: 0 };
+    let mut alice = server.connect().await;
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: far_away, sequence: 1 }).await;
+
+    let mut bob = server.connect().await;
+    let cosmetics = Cosmetics { color: RobotColor::Red, accessory: Accessory::None };
+
+    for _ in 0..Cosmetics::default().price_of(&cosmetics) {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.send(SystemMessages::ChangeName { name: "Hermit".into() }).await;
+    alice.send(SystemMessages::ChangeCosmetics { cosmetics }).await;
+    alice.send(SystemMessages::Emote { kind: EmoteKind::Wave }).await;
+
+    alice.expect(|message| matches!(message, SystemMessages::PlayerCosmeticsChanged { .. }).then_some(())).await;
+
+    bob.send(SystemMessages::Ping).await;
+
+    bob.expect(|message| match message {
+        SystemMessages::PlayerRenamed { .. } | SystemMessages::PlayerCosmeticsChanged { .. } | SystemMessages::EnemyEmote { .. } => {
+            panic!("bob heard about alice from afar: {message:?}")
+        }
+        SystemMessages::Pong => Some(()),
+        _ => None,
+    }).await;
+
+    // Alice looks up to date once she comes into view
+    bob.send(SystemMessages::PlayerPosition { coordinate: far_away, sequence: 1 }).await;
+
+    let spawned = bob.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(data),
+        _ => None,
+    }).await;
+
+    assert_eq!((spawned.id, spawned.name.as_str(), spawned.cosmetics), (alice.id, "Hermit", cosmetics));
+}
+
+#[tokio::test]
+async fn overlapping_monuments_are_rejected() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    for player in [&mut alice, &mut bob] {
+        for _ in 0..5 {
+            player.send(SystemMessages::MainPlayerPickedUpToken).await;
+        }
+    }
+
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a tower".into(), coordinate: Coordinate { x: 5, y: 5 } }).await;
+
+    bob.expect(|message| match message {
+        SystemMessages::BuildMonument { .. } => Some(()),
+        _ => None,
+    }).await;
+
+    // Right on top of it, then only sharing the edge of its footprint, both well within reach
+    for coordinate in [Coordinate { x: 7, y: 4 }, Coordinate { x: 5 + MONUMENT_FOOTPRINT * 2, y: 5 }] {
+        bob.send(SystemMessages::BuildMonumentRequest { prompt: "a bridge".into(), coordinate }).await;
+
+        let reason = bob.expect(|message| match message {
+            SystemMessages::BuildMonumentRejected { reason } => Some(reason),
+            _ => None,
+        }).await;
+
+        assert_eq!(reason, "there is already a monument there");
+    }
+}
+
+#[tokio::test]
+async fn monuments_are_streamed_by_chunk() {
+    let server = TestServer::start().await;
+
+    let far_away = Coordinate { x: CHUNK_SIZE * 2, y: CHUNK_SIZE * 2 };
+    let mut alice = server.connect().await;
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: far_away, sequence: 1 }).await;
+
+    for _ in 0..5 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a distant castle".into(), coordinate: far_away }).await;
+
+    let id = alice.expect(|message| match message {
+        SystemMessages::BuildMonument { monument } => Some(monument.id),
+        _ => None,
+    }).await;
+
+    let mut bob = server.connect().await;
+    let next_to_the_castle = Coordinate { x: far_away.x + MONUMENT_FOOTPRINT + 1, y: far_away.y };
+
+    bob.send(SystemMessages::PlayerPosition { coordinate: next_to_the_castle, sequence: 1 }).await;
+
+    // Nothing is sent about the castle until bob leaves the chunks around the spawn
+    let first = bob.expect(|message| match message {
+        SystemMessages::ChunkUnloaded { .. } => Some(None),
+        SystemMessages::BuildMonument { monument } => Some(Some(monument.id)),
+        _ => None,
+    }).await;
+
+    assert_eq!(first, None);
+
+    let streamed = bob.expect(|message| match message {
+        SystemMessages::BuildMonument { monument } => Some(monument.id),
+        _ => None,
+    }).await;
+
+    assert_eq!(streamed, id);
+}
+
+#[tokio::test]
+async fn robots_are_only_shown_to_players_nearby() {
+    let server = TestServer::start().await;
+
+    let far_away = Coordinate { x: CHUNK_SIZE * 2, y: 0 };
+    let mut alice = server.connect().await;
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: far_away, sequence: 1 }).await;
+    alice.send(SystemMessages::Ping).await;
+    alice.expect(|message| matches!(message, SystemMessages::Pong).then_some(())).await;
+
+    let mut bob = server.connect().await;
+
+    // Alice is too far away to be spawned
+    bob.send(SystemMessages::Ping).await;
+
+    let spawned = bob.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(Some(data.id)),
+        SystemMessages::Pong => Some(None),
+        _ => None,
+    }).await;
+
+    assert_eq!(spawned, None);
+
+    bob.send(SystemMessages::PlayerPosition { coordinate: far_away, sequence: 1 }).await;
+
+    let spawned = bob.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } => Some(data),
+        _ => None,
+    }).await;
+
+    assert_eq!((spawned.id, spawned.position), (alice.id, far_away));
+
+    let spawned = alice.expect(|message| match message {
+        SystemMessages::EnemyPlayerSpawn { data } if data.id == bob.id => Some(data.position),
+        _ => None,
+    }).await;
+
+    assert_eq!(spawned, far_away);
+
+    bob.send(SystemMessages::PlayerPosition { coordinate: Coordinate::default(), sequence: 2 }).await;
+
+    let gone = alice.expect(|message| match message {
+        SystemMessages::EnemyOutOfView { id } => Some(id),
+        _ => None,
+    }).await;
+
+    assert_eq!(gone, bob.id);
+}
+
+#[tokio::test]
+async fn robots_cannot_walk_into_monuments() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.connect().await;
+    let castle = Coordinate { x: 6, y: 0 };
+
+    for _ in 0..5 {
+        alice.send(SystemMessages::MainPlayerPickedUpToken).await;
+    }
+
+    alice.send(SystemMessages::BuildMonumentRequest { prompt: "a castle".into(), coordinate: castle }).await;
+
+    alice.expect(|message| match message {
+        SystemMessages::BuildMonument { .. } => Some(()),
+        _ => None,
+    }).await;
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: castle, sequence: 1 }).await;
+
+    let acknowledged = |message: SystemMessages| match message {
+        SystemMessages::MoveAcknowledged { sequence, coordinate } => Some((sequence, coordinate)),
+        _ => None,
+    };
+
+    // The robot stays where it was
+    assert_eq!(alice.expect(acknowledged).await, (1, Coordinate::default()));
+
+    // Going around it is fine
+    let behind = Coordinate { x: castle.x + MONUMENT_FOOTPRINT + 1, y: 0 };
+
+    alice.send(SystemMessages::PlayerPosition { coordinate: behind, sequence: 2 }).await;
+
+    assert_eq!(alice.expect(acknowledged).await, (2, behind));
+
+    // Nobody can point that far
+    alice.send(SystemMessages::PlayerPosition { coordinate: Coordinate { x: i32::MAX, y: i32::MIN }, sequence: 3 }).await;
+
+    assert_eq!(alice.expect(acknowledged).await, (3, behind));
+}
@@ -0,0 +1,144 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use tempfile::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use server::admission::{Capacity, ConnectionsPerIp};
+use server::api::build_server;
+use server::game_loop::GameLoop;
+use server::generator::StubGenerator;
+use server::websocket::{self, Gateway};
+use server::world::World;
+use shared::{PlayerId, SystemMessages};
+
+/// How long a client waits for an expected message before failing the test
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The HTTP and legacy websocket servers listening on ephemeral ports, stopped once dropped
+pub struct TestServer {
+    pub http: SocketAddr,
+    pub legacy: SocketAddr,
+    _stop: oneshot::Sender<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        isolate_working_directory();
+
+        let game = GameLoop::spawn(World::default(), Capacity::default(), StubGenerator::default());
+        let gateway = Gateway::new(game.clone(), ConnectionsPerIp::new(usize::MAX));
+        let metrics = PrometheusBuilder::new().build_recorder().handle();
+
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let legacy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let http = http_listener.local_addr().unwrap();
+        let legacy = legacy_listener.local_addr().unwrap();
+
+        let (stop, stopped) = oneshot::channel::<()>();
+
+        tokio::spawn(websocket::accept(legacy_listener, gateway.clone()));
+        tokio::spawn(build_server(http_listener, game, metrics, None, gateway, async move {
+            let _ = stopped.await;
+        }));
+
+        Self { http, legacy, _stop: stop }
+    }
+
+    /// Connect through the `/ws` route and wait for the main player to be spawned
+    pub async fn connect(&self) -> TestClient {
+        TestClient::connect(format!("ws://{}/ws", self.http)).await
+    }
+
+    /// Connect through the dedicated websocket port
+    pub async fn connect_legacy(&self) -> TestClient {
+        TestClient::connect(format!("ws://{}", self.legacy)).await
+    }
+
+    /// Deliver the generated image the way ComfyUI does once it's done
+    pub async fn complete_generation(&self, id: u32) -> reqwest::StatusCode {
+        let boundary = "imaginarium-test-boundary";
+
+        let body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"prompt_id\"\r\n\r\n\
+            {id}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"monument.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            not really a png\r\n\
+            --{boundary}--\r\n"
+        );
+
+        reqwest::Client::new()
+            .post(format!("http://{}/generation", self.http))
+            .header("content-type", format!("multipart/form-data; boundary={boundary}"))
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+}
+
+/// A scripted player speaking the `shared` codec
+pub struct TestClient {
+    pub id: PlayerId,
+    websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl TestClient {
+    async fn connect(url: String) -> Self {
+        let (websocket, _) = connect_async(url).await.unwrap();
+
+        let mut client = Self { id: PlayerId::from(0), websocket };
+
+        client.id = client.expect(|message| match message {
+            SystemMessages::MainPlayerSpawn { data } => Some(data.id),
+            _ => None,
+        }).await;
+
+        client
+    }
+
+    pub async fn send(&mut self, message: SystemMessages) {
+        self.websocket.send(message.into()).await.unwrap();
+    }
+
+    /// Read messages until one is accepted by `matches`, anything else is skipped
+    pub async fn expect<T>(&mut self, mut matches: impl FnMut(SystemMessages) -> Option<T>) -> T {
+        let receive = async {
+            loop {
+                let frame = self.websocket.next().await.expect("connection closed").unwrap();
+
+                if let Some(found) = SystemMessages::try_from(frame).ok().and_then(&mut matches) {
+                    return found;
+                }
+            }
+        };
+
+        tokio::time::timeout(EXPECT_TIMEOUT, receive).await.expect("expected message never arrived")
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.websocket.close(None).await;
+    }
+}
+
+/// The webhook and the monument cache write to `./assets`, keep that out of the source tree
+fn isolate_working_directory() {
+    static DIRECTORY: OnceLock<TempDir> = OnceLock::new();
+
+    DIRECTORY.get_or_init(|| {
+        let directory = TempDir::new().unwrap();
+        std::fs::create_dir_all(directory.path().join("assets")).unwrap();
+        std::env::set_current_dir(directory.path()).unwrap();
+        directory
+    });
+}
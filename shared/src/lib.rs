@@ -9,6 +9,46 @@ use tokio_tungstenite_wasm::Message;
 #[cfg(not(target_arch = "wasm32"))]
 use tungstenite::Message;
 
+pub mod pathfinding;
+
+/// Side of a chunk, in tiles
+pub const CHUNK_SIZE: i32 = 32;
+
+/// Players are streamed the chunks within this many chunks of the one they stand in
+pub const VIEW_DISTANCE: i32 = 1;
+
+/// Square area of the world, monuments are streamed to players a chunk at a time
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub struct ChunkId {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ChunkId {
+    /// Chunks in view of a player standing in this one, itself included
+    pub fn neighborhood(self) -> impl Iterator<Item=ChunkId> {
+        (-VIEW_DISTANCE..=VIEW_DISTANCE).flat_map(move |x| {
+            (-VIEW_DISTANCE..=VIEW_DISTANCE).map(move |y| ChunkId { x: self.x + x, y: self.y + y })
+        })
+    }
+}
+
+/// How fast robots walk, in tiles per second
+pub const ROBOT_SPEED: f32 = 8.0;
+
+/// Monuments cover every tile within this many tiles of their coordinate
+pub const MONUMENT_FOOTPRINT: i32 = 2;
+
+/// How far from the builder a monument can be placed, in tiles
+pub const MAX_PLACEMENT_DISTANCE: f32 = 15.0;
+
+/// Furthest a single move can go, in tiles. Players only see the chunks around theirs, so they
+/// can't point any further than that
+pub const MAX_MOVE_DISTANCE: f32 = (CHUNK_SIZE * (2 * VIEW_DISTANCE + 1)) as f32;
+
+/// Tokens spent on a single monument
+pub const MONUMENT_PRICE: u32 = 5;
+
 #[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
 pub struct Coordinate {
     pub x: i32,
@@ -20,11 +60,31 @@ impl Coordinate {
         Vec3::new(self.x as f32, 0.0, self.y as f32)
     }
 
-    pub fn drift_by(mut self, amount: i32) -> Coordinate {
-        self.x -= amount;
-        self.y -= amount;
+    /// Straight line distance between both coordinates
+    pub fn distance(&self, other: &Coordinate) -> f32 {
+        let x = (i64::from(self.x) - i64::from(other.x)) as f32;
+        let y = (i64::from(self.y) - i64::from(other.y)) as f32;
 
-        self
+        (x * x + y * y).sqrt()
+    }
+
+    pub fn chunk(&self) -> ChunkId {
+        ChunkId {
+            x: self.x.div_euclid(CHUNK_SIZE),
+            y: self.y.div_euclid(CHUNK_SIZE),
+        }
+    }
+
+    /// Tiles covered by a monument standing on this coordinate
+    pub fn footprint(self) -> impl Iterator<Item=Coordinate> {
+        (-MONUMENT_FOOTPRINT..=MONUMENT_FOOTPRINT).flat_map(move |x| {
+            (-MONUMENT_FOOTPRINT..=MONUMENT_FOOTPRINT).map(move |y| Coordinate { x: self.x + x, y: self.y + y })
+        })
+    }
+
+    /// Whether monuments standing on both coordinates would share a tile
+    pub fn footprints_overlap(&self, other: &Coordinate) -> bool {
+        (self.x - other.x).abs() <= MONUMENT_FOOTPRINT * 2 && (self.y - other.y).abs() <= MONUMENT_FOOTPRINT * 2
     }
 }
 
@@ -37,7 +97,8 @@ impl From<Vec3> for Coordinate {
     }
 }
 
-#[derive(Component, Debug, Copy, Clone, Hash, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+#[derive(Component, Debug, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+#[serde(transparent)]
 pub struct PlayerId(u32);
 
 impl PlayerId {
@@ -46,11 +107,110 @@ impl PlayerId {
     }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+impl From<u32> for PlayerId {
+    fn from(value: u32) -> Self {
+        PlayerId(value)
+    }
+}
+
+impl From<PlayerId> for u32 {
+    fn from(value: PlayerId) -> Self {
+        value.0
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
 pub struct PlayerData {
     pub id: PlayerId,
+    /// Display name, unique among connected players
+    pub name: String,
     pub balance: u32,
     pub position: Coordinate,
+    pub cosmetics: Cosmetics,
+}
+
+/// Tint of the robot's body
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub enum RobotColor {
+    #[default]
+    Yellow,
+    Red,
+    Green,
+    Blue,
+    Purple,
+    Black,
+}
+
+impl RobotColor {
+    pub const ALL: [RobotColor; 6] = [
+        RobotColor::Yellow,
+        RobotColor::Red,
+        RobotColor::Green,
+        RobotColor::Blue,
+        RobotColor::Purple,
+        RobotColor::Black,
+    ];
+
+    /// Tokens spent to switch to this color
+    pub fn price(&self) -> u32 {
+        match self {
+            RobotColor::Yellow => 0,
+            RobotColor::Black => 10,
+            _ => 3,
+        }
+    }
+
+    /// Linear RGB
+    pub fn rgb(&self) -> [f32; 3] {
+        match self {
+            RobotColor::Yellow => [0.96, 0.76, 0.05],
+            RobotColor::Red => [0.86, 0.12, 0.12],
+            RobotColor::Green => [0.15, 0.68, 0.28],
+            RobotColor::Blue => [0.15, 0.39, 0.92],
+            RobotColor::Purple => [0.55, 0.24, 0.86],
+            RobotColor::Black => [0.06, 0.06, 0.07],
+        }
+    }
+}
+
+/// Worn on top of the robot's head
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub enum Accessory {
+    #[default]
+    None,
+    TopHat,
+    Crown,
+    Halo,
+}
+
+impl Accessory {
+    pub const ALL: [Accessory; 4] = [Accessory::None, Accessory::TopHat, Accessory::Crown, Accessory::Halo];
+
+    /// Tokens spent to switch to this accessory
+    pub fn price(&self) -> u32 {
+        match self {
+            Accessory::None => 0,
+            Accessory::TopHat => 5,
+            Accessory::Crown => 15,
+            Accessory::Halo => 10,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub struct Cosmetics {
+    pub color: RobotColor,
+    pub accessory: Accessory,
+}
+
+impl Cosmetics {
+    /// Tokens spent to go from the current loadout to `next`, only the parts that change are paid for
+    pub fn price_of(&self, next: &Cosmetics) -> u32 {
+        let color = if self.color == next.color { 0 } else { next.color.price() };
+        let accessory = if self.accessory == next.accessory { 0 } else { next.accessory.price() };
+
+        color + accessory
+    }
 }
 
 #[derive(Component, Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
@@ -60,10 +220,55 @@ pub struct Monument {
     pub description: String,
     pub position: Coordinate,
     pub under_construction: bool,
+    /// Hidden monuments are kept in the world but never sent to players
+    #[serde(default)]
+    pub hidden: bool,
+    /// Player who built it, ids only live as long as the connection so this is mostly informative
+    #[serde(default)]
+    pub owner: Option<PlayerId>,
+    /// Display name of the owner when it was built, empty for monuments that predate ownership
+    #[serde(default)]
+    pub owner_name: String,
+    /// Seconds since the Unix epoch, zero for monuments that predate ownership
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub enum ChatChannel {
+    /// Heard by every player in the world
+    #[default]
+    Global,
+    /// Only heard by players close to the speaker
+    Proximity,
+}
+
+/// Animations players can trigger on their robot, played once by everyone around
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
+pub enum EmoteKind {
+    Wave,
+    ThumbsUp,
+    Dance,
+    Yes,
+    No,
+    Punch,
+}
+
+impl EmoteKind {
+    pub const ALL: [EmoteKind; 6] = [
+        EmoteKind::Wave,
+        EmoteKind::ThumbsUp,
+        EmoteKind::Dance,
+        EmoteKind::Yes,
+        EmoteKind::No,
+        EmoteKind::Punch,
+    ];
 }
 
 #[derive(Debug, Clone, bincode::Encode, bincode::Decode)]
 pub enum SystemMessages {
+    // Variants are encoded by position, new ones go at the end so the others keep their
+    // discriminants. Payloads still change between releases, so clients and server must match
     Ping,
     Pong,
     Connected {
@@ -72,18 +277,86 @@ pub enum SystemMessages {
     Welcome {
         data: PlayerData,
     },
-
-    PlayerPosition { coordinate: Coordinate },
-    EnemyPosition { id: PlayerId, coordinate: Coordinate },
+    /// Moves are numbered by the player, so their acknowledgements can be matched with them
+    PlayerPosition { coordinate: Coordinate, sequence: u32 },
+    /// Stamped with the server time of the move, in milliseconds since the epoch, and the speed the
+    /// robot walks there at
+    EnemyPosition { id: PlayerId, coordinate: Coordinate, timestamp: u64, speed: f32 },
     EnemyDisconnected { id: PlayerId },
-
-    BuildMonumentRequest { prompt: String },
+    BuildMonumentRequest { prompt: String, coordinate: Coordinate },
     MonumentCompleted { id: u32, asset: String },
     BuildMonument { monument: Monument },
     MainPlayerPickedUpToken,
     MainPlayerCurrentBalance { balance: u32 },
     MainPlayerSpawn { data: PlayerData },
     EnemyPlayerSpawn { data: PlayerData },
+
+    ServerShuttingDown { reconnect_after: u32 },
+    Evicted { reason: String },
+    /// Messages of this kind are being dropped until `retry_after_ms` milliseconds have passed
+    RateLimited { kind: String, retry_after_ms: u32 },
+    /// The world is full, the player is admitted automatically once everyone ahead got in
+    WaitingRoom { position: u32 },
+    MonumentRemoved { id: u32 },
+    ChangeName { name: String },
+    PlayerRenamed { id: PlayerId, name: String },
+    NameRejected { reason: String },
+    ChangeCosmetics { cosmetics: Cosmetics },
+    PlayerCosmeticsChanged { id: PlayerId, cosmetics: Cosmetics },
+    CosmeticsRejected { reason: String },
+    SendChat { channel: ChatChannel, text: String },
+    Chat { id: PlayerId, name: String, channel: ChatChannel, text: String },
+    Emote { kind: EmoteKind },
+    EnemyEmote { id: PlayerId, kind: EmoteKind },
+    BuildMonumentRejected { reason: String },
+    /// The chunk went out of view, its monuments won't be kept up to date anymore
+    ChunkUnloaded { chunk: ChunkId },
+    /// The robot walked out of view, it is spawned again once it comes back
+    EnemyOutOfView { id: PlayerId },
+    /// Latest move of the player the server went through, and where the robot is as far as the
+    /// server is concerned. Rejected moves leave it where it was
+    MoveAcknowledged { sequence: u32, coordinate: Coordinate },
+}
+
+impl SystemMessages {
+    /// Name of the variant, used to label metrics and logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SystemMessages::Ping => "Ping",
+            SystemMessages::Pong => "Pong",
+            SystemMessages::Connected { .. } => "Connected",
+            SystemMessages::Welcome { .. } => "Welcome",
+            SystemMessages::PlayerPosition { .. } => "PlayerPosition",
+            SystemMessages::EnemyPosition { .. } => "EnemyPosition",
+            SystemMessages::EnemyDisconnected { .. } => "EnemyDisconnected",
+            SystemMessages::BuildMonumentRequest { .. } => "BuildMonumentRequest",
+            SystemMessages::MonumentCompleted { .. } => "MonumentCompleted",
+            SystemMessages::BuildMonument { .. } => "BuildMonument",
+            SystemMessages::MainPlayerPickedUpToken => "MainPlayerPickedUpToken",
+            SystemMessages::MainPlayerCurrentBalance { .. } => "MainPlayerCurrentBalance",
+            SystemMessages::MainPlayerSpawn { .. } => "MainPlayerSpawn",
+            SystemMessages::EnemyPlayerSpawn { .. } => "EnemyPlayerSpawn",
+            SystemMessages::ServerShuttingDown { .. } => "ServerShuttingDown",
+            SystemMessages::Evicted { .. } => "Evicted",
+            SystemMessages::RateLimited { .. } => "RateLimited",
+            SystemMessages::WaitingRoom { .. } => "WaitingRoom",
+            SystemMessages::MonumentRemoved { .. } => "MonumentRemoved",
+            SystemMessages::ChangeName { .. } => "ChangeName",
+            SystemMessages::PlayerRenamed { .. } => "PlayerRenamed",
+            SystemMessages::NameRejected { .. } => "NameRejected",
+            SystemMessages::ChangeCosmetics { .. } => "ChangeCosmetics",
+            SystemMessages::PlayerCosmeticsChanged { .. } => "PlayerCosmeticsChanged",
+            SystemMessages::CosmeticsRejected { .. } => "CosmeticsRejected",
+            SystemMessages::SendChat { .. } => "SendChat",
+            SystemMessages::Chat { .. } => "Chat",
+            SystemMessages::Emote { .. } => "Emote",
+            SystemMessages::EnemyEmote { .. } => "EnemyEmote",
+            SystemMessages::BuildMonumentRejected { .. } => "BuildMonumentRejected",
+            SystemMessages::ChunkUnloaded { .. } => "ChunkUnloaded",
+            SystemMessages::EnemyOutOfView { .. } => "EnemyOutOfView",
+            SystemMessages::MoveAcknowledged { .. } => "MoveAcknowledged",
+        }
+    }
 }
 
 impl TryFrom<Message> for SystemMessages {
@@ -102,3 +375,70 @@ impl Into<Message> for SystemMessages {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discriminant(message: SystemMessages) -> u8 {
+        bincode::encode_to_vec(message, standard()).unwrap()[0]
+    }
+
+    #[test]
+    fn discriminants_are_stable() {
+        let data = PlayerData { id: PlayerId(1), name: String::new(), balance: 0, position: Coordinate::default(), cosmetics: Cosmetics::default() };
+        let monument = Monument {
+            id: 1,
+            asset: String::new(),
+            description: String::new(),
+            position: Coordinate::default(),
+            under_construction: false,
+            hidden: false,
+            owner: None,
+            owner_name: String::new(),
+            created_at: 0,
+        };
+
+        let messages = [
+            SystemMessages::Ping,
+            SystemMessages::Pong,
+            SystemMessages::Connected { id: PlayerId(1) },
+            SystemMessages::Welcome { data: data.clone() },
+            SystemMessages::PlayerPosition { coordinate: Coordinate::default(), sequence: 0 },
+            SystemMessages::EnemyPosition { id: PlayerId(1), coordinate: Coordinate::default(), timestamp: 0, speed: 0.0 },
+            SystemMessages::EnemyDisconnected { id: PlayerId(1) },
+            SystemMessages::BuildMonumentRequest { prompt: String::new(), coordinate: Coordinate::default() },
+            SystemMessages::MonumentCompleted { id: 1, asset: String::new() },
+            SystemMessages::BuildMonument { monument },
+            SystemMessages::MainPlayerPickedUpToken,
+            SystemMessages::MainPlayerCurrentBalance { balance: 0 },
+            SystemMessages::MainPlayerSpawn { data: data.clone() },
+            SystemMessages::EnemyPlayerSpawn { data },
+            SystemMessages::ServerShuttingDown { reconnect_after: 0 },
+            SystemMessages::Evicted { reason: String::new() },
+            SystemMessages::RateLimited { kind: String::new(), retry_after_ms: 0 },
+            SystemMessages::WaitingRoom { position: 0 },
+            SystemMessages::MonumentRemoved { id: 1 },
+            SystemMessages::ChangeName { name: String::new() },
+            SystemMessages::PlayerRenamed { id: PlayerId(1), name: String::new() },
+            SystemMessages::NameRejected { reason: String::new() },
+            SystemMessages::ChangeCosmetics { cosmetics: Cosmetics::default() },
+            SystemMessages::PlayerCosmeticsChanged { id: PlayerId(1), cosmetics: Cosmetics::default() },
+            SystemMessages::CosmeticsRejected { reason: String::new() },
+            SystemMessages::SendChat { channel: ChatChannel::Global, text: String::new() },
+            SystemMessages::Chat { id: PlayerId(1), name: String::new(), channel: ChatChannel::Global, text: String::new() },
+            SystemMessages::Emote { kind: EmoteKind::Wave },
+            SystemMessages::EnemyEmote { id: PlayerId(1), kind: EmoteKind::Wave },
+            SystemMessages::BuildMonumentRejected { reason: String::new() },
+            SystemMessages::ChunkUnloaded { chunk: ChunkId::default() },
+            SystemMessages::EnemyOutOfView { id: PlayerId(1) },
+            SystemMessages::MoveAcknowledged { sequence: 0, coordinate: Coordinate::default() },
+        ];
+
+        // Pinned so adding a message never renumbers the ones before it
+        for (expected, message) in messages.into_iter().enumerate() {
+            let kind = message.kind();
+            assert_eq!(discriminant(message) as usize, expected, "{kind} moved");
+        }
+    }
+}
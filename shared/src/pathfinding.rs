@@ -0,0 +1,205 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::Coordinate;
+
+/// Tiles explored before giving up on a route, keeps far away or unreachable goals cheap
+pub const MAX_SEARCHED_TILES: usize = 8192;
+
+/// Cost of a straight step, diagonal steps cost `DIAGONAL_COST`. Costs are wide enough for any
+/// two coordinates, however far apart
+const STRAIGHT_COST: u64 = 10;
+const DIAGONAL_COST: u64 = 14;
+
+const NEIGHBOURS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+#[derive(PartialEq, Eq)]
+struct Candidate {
+    /// Cost so far plus the estimate left to the goal
+    score: u64,
+    estimate: u64,
+    tile: Coordinate,
+}
+
+impl Ord for Candidate {
+    /// Lowest score first, and closest to the goal among equal scores
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.score.cmp(&self.score).then_with(|| other.estimate.cmp(&self.estimate))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Octile distance, exact on an empty grid where diagonal moves are allowed
+fn estimate(from: Coordinate, to: Coordinate) -> u64 {
+    let x = u64::from(from.x.abs_diff(to.x));
+    let y = u64::from(from.y.abs_diff(to.y));
+
+    STRAIGHT_COST * x.max(y) + (DIAGONAL_COST - STRAIGHT_COST) * x.min(y)
+}
+
+/// Shortest route from `start` to `goal` going around `blocked` tiles, as the tiles where the route
+/// turns, ending with `goal` and without `start`. Diagonal steps can't cut the corner of a blocked
+/// tile, but robots standing on one can step out of it freely. `None` when the goal is blocked or
+/// couldn't be reached within [`MAX_SEARCHED_TILES`]
+pub fn find_path(start: Coordinate, goal: Coordinate, blocked: impl Fn(Coordinate) -> bool) -> Option<Vec<Coordinate>> {
+    if start == goal {
+        return Some(Vec::new());
+    }
+
+    if blocked(goal) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::from([Candidate { score: estimate(start, goal), estimate: estimate(start, goal), tile: start }]);
+    let mut costs = HashMap::from([(start, 0)]);
+    let mut came_from = HashMap::new();
+
+    while let Some(Candidate { score, estimate: left, tile }) = open.pop() {
+        if tile == goal {
+            return Some(waypoints(&came_from, start, goal));
+        }
+
+        let cost = costs[&tile];
+
+        // Already reached through a cheaper route
+        if score > cost + left {
+            continue;
+        }
+
+        if costs.len() > MAX_SEARCHED_TILES {
+            return None;
+        }
+
+        // Monuments can end up built on top of a robot, which is free to walk out of them
+        let stuck = blocked(tile);
+
+        for (x, y) in NEIGHBOURS {
+            // The edge of the grid is a wall like any other
+            let next = Coordinate { x: tile.x.saturating_add(x), y: tile.y.saturating_add(y) };
+
+            if next == tile || (!stuck && blocked(next)) {
+                continue;
+            }
+
+            let diagonal = x != 0 && y != 0;
+
+            if diagonal && !stuck && (blocked(Coordinate { x: next.x, y: tile.y }) || blocked(Coordinate { x: tile.x, y: next.y })) {
+                continue;
+            }
+
+            let next_cost = cost + if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+
+            if costs.get(&next).is_some_and(|known| *known <= next_cost) {
+                continue;
+            }
+
+            costs.insert(next, next_cost);
+            came_from.insert(next, tile);
+
+            let left = estimate(next, goal);
+            open.push(Candidate { score: next_cost + left, estimate: left, tile: next });
+        }
+    }
+
+    None
+}
+
+/// Walk the route back from the goal, keeping only the tiles where its direction changes
+fn waypoints(came_from: &HashMap<Coordinate, Coordinate>, start: Coordinate, goal: Coordinate) -> Vec<Coordinate> {
+    let mut route = vec![goal];
+    let mut tile = goal;
+
+    while let Some(previous) = came_from.get(&tile) {
+        if *previous != start {
+            route.push(*previous);
+        }
+
+        tile = *previous;
+    }
+
+    route.reverse();
+
+    let mut waypoints: Vec<Coordinate> = Vec::new();
+    let mut from = start;
+
+    for (index, tile) in route.iter().enumerate() {
+        let turns = route.get(index + 1).is_none_or(|next| {
+            (tile.x - from.x, tile.y - from.y) != (next.x - tile.x, next.y - tile.y)
+        });
+
+        if turns {
+            waypoints.push(*tile);
+        }
+
+        from = *tile;
+    }
+
+    waypoints
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn walks_straight_on_open_ground() {
+        let path = find_path(Coordinate::default(), Coordinate { x: 10, y: 0 }, |_| false);
+
+        assert_eq!(path, Some(vec![Coordinate { x: 10, y: 0 }]));
+    }
+
+    #[test]
+    fn goes_around_monuments() {
+        let monument = Coordinate { x: 5, y: 0 };
+        let blocked: HashSet<_> = monument.footprint().collect();
+
+        let path = find_path(Coordinate::default(), Coordinate { x: 10, y: 0 }, |tile| blocked.contains(&tile)).unwrap();
+
+        assert_eq!(path.last(), Some(&Coordinate { x: 10, y: 0 }));
+        assert!(path.len() > 1);
+
+        // Every leg of the route stays clear of the footprint
+        let mut from = Coordinate::default();
+
+        for waypoint in path {
+            while from != waypoint {
+                from = Coordinate { x: from.x + (waypoint.x - from.x).signum(), y: from.y + (waypoint.y - from.y).signum() };
+                assert!(!blocked.contains(&from));
+            }
+        }
+    }
+
+    #[test]
+    fn gives_up_on_unreachable_goals() {
+        let monument = Coordinate { x: 5, y: 0 };
+
+        assert_eq!(find_path(Coordinate::default(), monument, |tile| monument.footprint().any(|covered| covered == tile)), None);
+
+        // Walled in
+        let walled = |tile: Coordinate| tile.x.abs() == 3 || tile.y.abs() == 3;
+
+        assert_eq!(find_path(Coordinate::default(), Coordinate { x: 10, y: 10 }, walled), None);
+    }
+
+    #[test]
+    fn stays_within_the_grid() {
+        let edge = Coordinate { x: i32::MAX, y: i32::MIN };
+
+        assert_eq!(find_path(edge, Coordinate { x: i32::MAX - 5, y: i32::MIN }, |_| false), Some(vec![Coordinate { x: i32::MAX - 5, y: i32::MIN }]));
+        assert_eq!(find_path(edge, Coordinate { x: i32::MIN, y: i32::MAX }, |_| false), None);
+    }
+
+    #[test]
+    fn walks_out_of_a_monument_built_on_top() {
+        let blocked: HashSet<_> = Coordinate::default().footprint().collect();
+
+        assert!(find_path(Coordinate::default(), Coordinate { x: 10, y: 0 }, |tile| blocked.contains(&tile)).is_some());
+    }
+}